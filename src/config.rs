@@ -0,0 +1,220 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::dataset::DatasetSource;
+
+/// Read when `--config` isn't given, if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "films.toml";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config file {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+
+    #[error("failed to parse config file {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+}
+
+/// Where films are kept.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Dynamo,
+    Memory,
+}
+
+/// What to do with the films and index tables when the API starts.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StartupMode {
+    /// Create the tables if they're missing.
+    #[default]
+    Ensure,
+    /// Delete and recreate the tables, losing every film. Only for development.
+    Recreate,
+    /// Check the tables exist with the expected keys, and fail if they don't.
+    VerifyOnly,
+}
+
+/// Settings for the API. Each one comes from, in increasing priority, its
+/// default, the TOML config file, an environment variable and a CLI flag.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub store: Backend,
+    pub table: String,
+    pub region: String,
+    /// Sends DynamoDB requests here instead of the region's endpoint, e.g.
+    /// `http://localhost:8000` for DynamoDB Local.
+    pub endpoint: Option<String>,
+    pub listen: SocketAddr,
+    /// The most bytes `POST /films/batch` accepts.
+    pub batch_limit: u64,
+    /// Read and write capacity units for tables the API creates.
+    pub capacity: i64,
+    /// Load the films dataset into a newly created table.
+    pub seed: bool,
+    /// The films dataset to seed from: a path, `-` for stdin or
+    /// `s3://bucket/key`. Nothing is loaded if this isn't set.
+    pub dataset: Option<DatasetSource>,
+    /// How long to wait for tables to be created or deleted, in seconds.
+    pub table_wait_secs: u64,
+    /// How many batch writes seeding has in flight at once.
+    pub load_concurrency: usize,
+    /// How many segments listings and exports scan the table as at once.
+    pub scan_segments: u32,
+    /// Sends S3 requests here, with path-style addressing, instead of the
+    /// region's endpoint, e.g. for MinIO or LocalStack.
+    pub s3_endpoint: Option<String>,
+    pub startup: StartupMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            store: Backend::Dynamo,
+            table: "films".into(),
+            region: "us-east-1".into(),
+            endpoint: None,
+            listen: ([0, 0, 0, 0], 3030).into(),
+            batch_limit: crate::filters::DEFAULT_BATCH_LIMIT,
+            capacity: crate::ddb::CAPACITY,
+            seed: true,
+            dataset: None,
+            table_wait_secs: 300,
+            load_concurrency: crate::ddb::DEFAULT_LOAD_CONCURRENCY,
+            scan_segments: crate::ddb::scan::DEFAULT_SCAN_SEGMENTS,
+            s3_endpoint: None,
+            startup: StartupMode::Ensure,
+        }
+    }
+}
+
+#[derive(Debug, Default, Parser)]
+#[command(about = "A film query API backed by DynamoDB")]
+pub struct Cli {
+    /// TOML config file [default: films.toml, if it exists]
+    #[arg(long, env = "FILMS_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "FILMS_STORE")]
+    pub store: Option<Backend>,
+    #[arg(long, env = "FILMS_TABLE")]
+    pub table: Option<String>,
+    #[arg(long, env = "FILMS_REGION")]
+    pub region: Option<String>,
+    #[arg(long, env = "FILMS_ENDPOINT")]
+    pub endpoint: Option<String>,
+    #[arg(long, env = "FILMS_LISTEN")]
+    pub listen: Option<SocketAddr>,
+    #[arg(long, env = "FILMS_BATCH_LIMIT")]
+    pub batch_limit: Option<u64>,
+    #[arg(long, env = "FILMS_CAPACITY")]
+    pub capacity: Option<i64>,
+    #[arg(long, env = "FILMS_SEED")]
+    pub seed: Option<bool>,
+    /// Films dataset: a path, - for stdin or s3://bucket/key
+    #[arg(long, env = "FILMS_DATASET")]
+    pub dataset: Option<DatasetSource>,
+    #[arg(long, env = "FILMS_TABLE_WAIT_SECS")]
+    pub table_wait_secs: Option<u64>,
+    #[arg(long, env = "FILMS_LOAD_CONCURRENCY")]
+    pub load_concurrency: Option<usize>,
+    #[arg(long, env = "FILMS_SCAN_SEGMENTS")]
+    pub scan_segments: Option<u32>,
+    #[arg(long, env = "FILMS_S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+    #[arg(long, env = "FILMS_STARTUP")]
+    pub startup: Option<StartupMode>,
+}
+
+impl Config {
+    /// Loads the config file named by `cli`, then applies `cli` on top.
+    /// Clap has already let flags override environment variables.
+    pub fn load(cli: Cli) -> Result<Self, ConfigError> {
+        let config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+        Ok(config.merge(cli))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn merge(self, cli: Cli) -> Self {
+        Config {
+            store: cli.store.unwrap_or(self.store),
+            table: cli.table.unwrap_or(self.table),
+            region: cli.region.unwrap_or(self.region),
+            endpoint: cli.endpoint.or(self.endpoint),
+            listen: cli.listen.unwrap_or(self.listen),
+            batch_limit: cli.batch_limit.unwrap_or(self.batch_limit),
+            capacity: cli.capacity.unwrap_or(self.capacity),
+            seed: cli.seed.unwrap_or(self.seed),
+            dataset: cli.dataset.or(self.dataset),
+            table_wait_secs: cli.table_wait_secs.unwrap_or(self.table_wait_secs),
+            load_concurrency: cli.load_concurrency.unwrap_or(self.load_concurrency),
+            scan_segments: cli.scan_segments.unwrap_or(self.scan_segments),
+            s3_endpoint: cli.s3_endpoint.or(self.s3_endpoint),
+            startup: cli.startup.unwrap_or(self.startup),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::{Backend, Cli, Config, StartupMode};
+
+    #[test]
+    fn test_cli_overrides_file() {
+        let file: Config = toml::from_str(
+            r#"
+            table = "films-dev"
+            endpoint = "http://localhost:8000"
+            listen = "127.0.0.1:8080"
+            seed = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.region, "us-east-1");
+
+        let cli = Cli::try_parse_from(["films-api", "--table", "films-test", "--store", "memory"])
+            .unwrap();
+        let config = file.merge(cli);
+
+        assert_eq!(config.table, "films-test");
+        assert_eq!(config.store, Backend::Memory);
+        assert_eq!(config.endpoint.as_deref(), Some("http://localhost:8000"));
+        assert_eq!(config.listen, ([127, 0, 0, 1], 8080).into());
+        assert!(!config.seed);
+        assert!(toml::from_str::<Config>("port = 1").is_err());
+        assert!(toml::from_str::<Config>(r#"dataset = "s3://films""#).is_err());
+    }
+
+    #[test]
+    fn test_startup_mode() {
+        assert_eq!(Config::default().startup, StartupMode::Ensure);
+
+        let file: Config = toml::from_str(r#"startup = "verify-only""#).unwrap();
+        assert_eq!(file.startup, StartupMode::VerifyOnly);
+
+        let cli = Cli::try_parse_from(["films-api", "--startup", "recreate"]).unwrap();
+        assert_eq!(file.merge(cli).startup, StartupMode::Recreate);
+    }
+}
@@ -0,0 +1,320 @@
+use std::{fmt, io::BufReader, path::PathBuf, str::FromStr};
+
+use serde::{
+    de::{SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use thiserror::Error;
+use tokio::{
+    io::AsyncRead,
+    sync::mpsc::{self, Receiver, Sender},
+};
+use tokio_util::io::SyncIoBridge;
+use tracing::debug;
+
+use crate::models::Film;
+
+/// How many parsed chunks can wait to be written before parsing pauses.
+const BUFFERED_CHUNKS: usize = 4;
+
+#[derive(Error, Debug)]
+pub enum DatasetError {
+    #[error("invalid dataset source {0}: expected a path, - or s3://bucket/key")]
+    InvalidSource(String),
+
+    #[error("failed to open dataset {0}: {1}")]
+    Open(DatasetSource, std::io::Error),
+
+    #[error("failed to fetch dataset {0}: {1}")]
+    S3(DatasetSource, Box<aws_sdk_s3::Error>),
+
+    #[error("failed to parse dataset: {0}")]
+    Parse(#[from] serde_json::Error),
+}
+
+/// Where to read the films dataset from: a JSON array of films in the
+/// Wikipedia Movie Data format.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DatasetSource {
+    File(PathBuf),
+    /// Written as `-`.
+    Stdin,
+    /// Written as `s3://bucket/key`.
+    S3 {
+        bucket: String,
+        key: String,
+    },
+}
+
+impl FromStr for DatasetSource {
+    type Err = DatasetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" {
+            return Ok(DatasetSource::Stdin);
+        }
+        match s.strip_prefix("s3://") {
+            Some(location) => match location.split_once('/') {
+                Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+                    Ok(DatasetSource::S3 {
+                        bucket: bucket.into(),
+                        key: key.into(),
+                    })
+                }
+                _ => Err(DatasetError::InvalidSource(s.into())),
+            },
+            None if s.is_empty() => Err(DatasetError::InvalidSource(s.into())),
+            None => Ok(DatasetSource::File(s.into())),
+        }
+    }
+}
+
+impl TryFrom<String> for DatasetSource {
+    type Error = DatasetError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for DatasetSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatasetSource::File(path) => write!(f, "{}", path.display()),
+            DatasetSource::Stdin => write!(f, "-"),
+            DatasetSource::S3 { bucket, key } => write!(f, "s3://{bucket}/{key}"),
+        }
+    }
+}
+
+/// A dataset source and the S3 client to read it with, if it's in S3.
+#[derive(Clone, Debug)]
+pub struct Dataset {
+    pub source: DatasetSource,
+    pub s3: aws_sdk_s3::Client,
+}
+
+impl Dataset {
+    pub fn new(source: DatasetSource, s3: aws_sdk_s3::Client) -> Self {
+        Dataset { source, s3 }
+    }
+
+    /// Starts reading the dataset in chunks of up to `chunk_size` films,
+    /// parsing films as they arrive rather than holding the whole file in
+    /// memory.
+    pub async fn chunks(
+        &self,
+        chunk_size: usize,
+    ) -> Result<Receiver<Result<Vec<Film>, DatasetError>>, DatasetError> {
+        Ok(stream_chunks(self.open().await?, chunk_size))
+    }
+
+    async fn open(&self) -> Result<Box<dyn AsyncRead + Send + Unpin>, DatasetError> {
+        debug!("Opening dataset {}", self.source);
+        match &self.source {
+            DatasetSource::File(path) => {
+                let file = tokio::fs::File::open(path)
+                    .await
+                    .map_err(|e| DatasetError::Open(self.source.clone(), e))?;
+                Ok(Box::new(file))
+            }
+            DatasetSource::Stdin => Ok(Box::new(tokio::io::stdin())),
+            DatasetSource::S3 { bucket, key } => {
+                let object = self
+                    .s3
+                    .get_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .map_err(|e| DatasetError::S3(self.source.clone(), Box::new(e.into())))?;
+                Ok(Box::new(object.body.into_async_read()))
+            }
+        }
+    }
+}
+
+/// Parses a JSON array of films from `reader` on a blocking thread, sending
+/// them on the returned channel in chunks of `chunk_size`, the last of which
+/// may be shorter. A parse error is sent last.
+///
+/// Parsing pauses while `BUFFERED_CHUNKS` chunks are waiting, so at most a
+/// few chunks of films are in memory however large the dataset is.
+pub fn stream_chunks<R>(reader: R, chunk_size: usize) -> Receiver<Result<Vec<Film>, DatasetError>>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    assert!(chunk_size > 0, "chunks must hold at least one film");
+    let (tx, rx) = mpsc::channel(BUFFERED_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        // serde_json reads a byte at a time, so buffer the bridged reads.
+        let reader = BufReader::new(SyncIoBridge::new(reader));
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        let sink = FilmSink {
+            tx: &tx,
+            chunk_size,
+        };
+        let parsed = deserializer
+            .deserialize_seq(sink)
+            .and_then(|_| deserializer.end());
+        if let Err(e) = parsed {
+            let _ = tx.blocking_send(Err(e.into()));
+        }
+    });
+    rx
+}
+
+// Sends films on a chunk at a time as they're parsed, instead of collecting
+// them into a Vec.
+struct FilmSink<'a> {
+    tx: &'a Sender<Result<Vec<Film>, DatasetError>>,
+    chunk_size: usize,
+}
+
+impl<'de> Visitor<'de> for FilmSink<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of films")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        while let Some(film) = seq.next_element::<Film>()? {
+            chunk.push(film);
+            if chunk.len() == self.chunk_size {
+                let full = std::mem::replace(&mut chunk, Vec::with_capacity(self.chunk_size));
+                if self.tx.blocking_send(Ok(full)).is_err() {
+                    // Nobody is listening any more.
+                    return Ok(());
+                }
+            }
+        }
+        if !chunk.is_empty() {
+            let _ = self.tx.blocking_send(Ok(chunk));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use aws_sdk_s3::config::{Credentials, Region};
+    use aws_smithy_client::test_connection::infallible_connection_fn;
+
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+    use super::{stream_chunks, Dataset, DatasetSource};
+
+    const DATASET: &str = r#"[
+        {"title": "Knives Out", "year": 2019, "cast": ["Daniel Craig"], "genres": ["Mystery"]},
+        {"title": "Heat", "year": 1995}
+    ]"#;
+
+    async fn collect(dataset: &Dataset) -> Vec<String> {
+        let mut chunks = dataset.chunks(25).await.unwrap();
+        let mut titles = Vec::new();
+        while let Some(chunk) = chunks.recv().await {
+            titles.extend(chunk.unwrap().into_iter().map(|film| film.title));
+        }
+        titles
+    }
+
+    // An endless stream of films, after the opening bracket.
+    struct EndlessFilms(usize);
+
+    impl AsyncRead for EndlessFilms {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            const FILM: &[u8] = br#"{"title": "Heat", "year": 1995},"#;
+            while buf.remaining() > 0 {
+                buf.put_slice(&[FILM[self.0 % FILM.len()]]);
+                self.0 += 1;
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!("-".parse::<DatasetSource>().unwrap(), DatasetSource::Stdin);
+        assert_eq!(
+            "s3://films-data/movies/2010s.json"
+                .parse::<DatasetSource>()
+                .unwrap(),
+            DatasetSource::S3 {
+                bucket: "films-data".into(),
+                key: "movies/2010s.json".into()
+            }
+        );
+        assert_eq!(
+            "data/movies.json".parse::<DatasetSource>().unwrap(),
+            DatasetSource::File("data/movies.json".into())
+        );
+        assert!("s3://films-data".parse::<DatasetSource>().is_err());
+        assert!("s3:///movies.json".parse::<DatasetSource>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_chunks() {
+        let json = br#"[
+            {"title": "Heat", "year": 1995},
+            {"title": "Ronin", "year": 1998},
+            {"title": "Collateral", "year": 2004}
+        ]"#;
+        let mut chunks = stream_chunks(&json[..], 2);
+        assert_eq!(chunks.recv().await.unwrap().unwrap().len(), 2);
+        assert_eq!(chunks.recv().await.unwrap().unwrap()[0].title, "Collateral");
+        assert!(chunks.recv().await.is_none());
+
+        let mut chunks = stream_chunks(
+            &br#"[{"title": "Heat", "year": 1995}, {"title": 1}]"#[..],
+            2,
+        );
+        assert!(chunks.recv().await.unwrap().is_err());
+        assert!(chunks.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stream_chunks_is_lazy() {
+        // This would never finish if the whole array were read first.
+        let reader = (&b"["[..]).chain(EndlessFilms(0));
+        let mut chunks = stream_chunks(reader, 25);
+        for _ in 0..100 {
+            let chunk = chunks.recv().await.unwrap().unwrap();
+            assert_eq!(chunk.len(), 25);
+            assert_eq!(chunk[24].title, "Heat");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_s3_dataset() {
+        // Stands in for S3, answering every GetObject with the dataset.
+        let connector = infallible_connection_fn(|request| {
+            assert_eq!(request.uri().path(), "/films-data/movies.json");
+            http::Response::builder().status(200).body(DATASET).unwrap()
+        });
+        let config = aws_sdk_s3::Config::builder()
+            .http_connector(connector)
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .endpoint_url("http://localhost:9000")
+            .force_path_style(true)
+            .build();
+        let dataset = Dataset::new(
+            "s3://films-data/movies.json".parse().unwrap(),
+            aws_sdk_s3::Client::from_conf(config),
+        );
+
+        assert_eq!(collect(&dataset).await, ["Knives Out", "Heat"]);
+    }
+}
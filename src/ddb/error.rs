@@ -7,15 +7,37 @@ use std::error::Error as StdError;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("table was not ready after several attempts: {0}")]
-    TableNotReady(String),
+    #[error("table {0} was not ready in time, last status: {1}")]
+    TableNotReady(String, String),
+    #[error("stopped waiting for table {0}")]
+    Cancelled(String),
+    #[error("table does not exist: {0}")]
+    TableMissing(String),
+    #[error("table {0} does not have the expected schema: {1}")]
+    SchemaMismatch(String, String),
+    #[error("gave up on {1} unprocessed items in {0}")]
+    Throttled(String, usize),
+    #[error("failed to load {failed} items, first error: {}", .errors[0])]
+    BulkLoad { failed: usize, errors: Vec<Error> },
     #[error("unhandled error")]
     Unhandled(#[source] Box<dyn StdError + Send + Sync + 'static>),
 }
 
 impl Error {
-    pub fn table_not_ready(table_name: impl Into<String>) -> Self {
-        Self::TableNotReady(table_name.into())
+    pub fn table_not_ready(table_name: impl Into<String>, status: impl Into<String>) -> Self {
+        Self::TableNotReady(table_name.into(), status.into())
+    }
+
+    pub fn table_missing(table_name: impl Into<String>) -> Self {
+        Self::TableMissing(table_name.into())
+    }
+
+    pub fn schema_mismatch(table_name: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::SchemaMismatch(table_name.into(), reason.into())
+    }
+
+    pub fn throttled(table_name: impl Into<String>, unprocessed: usize) -> Self {
+        Self::Throttled(table_name.into(), unprocessed)
     }
 
     pub fn unhandled(source: impl Into<Box<dyn StdError + Send + Sync + 'static>>) -> Self {
@@ -0,0 +1,47 @@
+use aws_sdk_dynamodb::{
+    config::{retry::RetryConfig, Credentials, Region},
+    Client,
+};
+use aws_smithy_client::test_connection::infallible_connection_fn;
+use serde_json::Value;
+
+/// A DynamoDB stand-in that answers every request with `handler`, given the
+/// operation's name, e.g. `BatchGetItem`, and the request body. It returns
+/// the status and body of the response. The SDK's own retries are off so
+/// that tests see every response.
+pub(crate) fn client<F>(handler: F) -> Client
+where
+    F: Fn(&str, &Value) -> (u16, Value) + Send + Sync + 'static,
+{
+    let connector = infallible_connection_fn(move |request| {
+        let operation = request
+            .headers()
+            .get("x-amz-target")
+            .and_then(|target| target.to_str().ok())
+            .and_then(|target| target.split('.').nth(1))
+            .unwrap_or_default()
+            .to_string();
+        let body: Value = serde_json::from_slice(request.body().bytes().unwrap_or(b"{}")).unwrap();
+        let (status, body) = handler(&operation, &body);
+        http::Response::builder()
+            .status(status)
+            .header("content-type", "application/x-amz-json-1.0")
+            .body(body.to_string())
+            .unwrap()
+    });
+    let config = aws_sdk_dynamodb::Config::builder()
+        .http_connector(connector)
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+        .retry_config(RetryConfig::disabled())
+        .build();
+    Client::from_conf(config)
+}
+
+/// The body of a DynamoDB error response, e.g. for `ValidationException`.
+pub(crate) fn error(error: &str, message: &str) -> Value {
+    serde_json::json!({
+        "__type": format!("com.amazonaws.dynamodb.v20120810#{error}"),
+        "message": message,
+    })
+}
@@ -0,0 +1,304 @@
+use std::time::{Duration, Instant};
+
+use aws_sdk_dynamodb::{types::WriteRequest, Client};
+use futures::{stream, StreamExt};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, warn};
+
+use super::{error::Error, index_put, index_table_name, index_terms, write_batch, CHUNK_SIZE};
+use crate::{dataset::Dataset, models::Film};
+
+/// How many batch writes `bulk_load_data` has in flight, unless configured.
+pub const DEFAULT_LOAD_CONCURRENCY: usize = 8;
+
+// How often a running load reports its progress.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Loads every film in `dataset` into `table_name` and the index table as
+/// films are parsed, with up to `concurrency` batch writes at once.
+///
+/// A batch that fails doesn't stop the load. The failures are reported
+/// together once every other batch has been written.
+pub async fn bulk_load_data(
+    client: &Client,
+    table_name: &str,
+    dataset: &Dataset,
+    concurrency: usize,
+) -> Result<(), Error> {
+    info!("Loading {} into table {table_name}", dataset.source);
+    let index_table = index_table_name(table_name);
+    let chunks = dataset.chunks(CHUNK_SIZE).await.map_err(Error::unhandled)?;
+
+    let mut writes = ReceiverStream::new(chunks)
+        .flat_map(|chunk| {
+            stream::iter(match chunk {
+                Ok(films) => batches(table_name, &index_table, &films),
+                Err(e) => vec![Batch::Failed(0, Error::unhandled(e))],
+            })
+        })
+        .map(|batch| async move {
+            match batch {
+                Batch::Write(table, ops) => {
+                    (table, ops.len(), write_batch(client, table, &ops).await)
+                }
+                Batch::Failed(count, e) => (table_name, count, Err(e)),
+            }
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut progress = Progress::new(table_name);
+    while let Some((table, count, result)) = writes.next().await {
+        progress.record(table, count, result);
+        progress.report_every(PROGRESS_INTERVAL);
+    }
+    progress.finish()
+}
+
+enum Batch<'a> {
+    /// Items to write to a table.
+    Write(&'a str, Vec<WriteRequest>),
+    /// Films that couldn't be turned into a batch.
+    Failed(usize, Error),
+}
+
+// Up to CHUNK_SIZE films become one batch for the films table, and their
+// index items as many batches as they need.
+fn batches<'a>(table_name: &'a str, index_table: &'a str, films: &[Film]) -> Vec<Batch<'a>> {
+    let mut ops = Vec::with_capacity(films.len());
+    let mut batches = Vec::new();
+    for film in films {
+        match film.try_into() {
+            Ok(put) => ops.push(WriteRequest::builder().put_request(put).build()),
+            Err(e) => batches.push(Batch::Failed(1, Error::unhandled(e))),
+        }
+    }
+    if !ops.is_empty() {
+        batches.push(Batch::Write(table_name, ops));
+    }
+
+    let index_ops = films
+        .iter()
+        .flat_map(|film| {
+            index_terms(film)
+                .into_iter()
+                .map(move |term| index_put(film, &term))
+        })
+        .collect::<Vec<WriteRequest>>();
+    batches.extend(
+        index_ops
+            .chunks(CHUNK_SIZE)
+            .map(|chunk| Batch::Write(index_table, chunk.to_vec())),
+    );
+    batches
+}
+
+/// Counts written and failed films, and index items apart from them so that
+/// films with long casts don't inflate the load's progress. Errors are kept
+/// for the end.
+struct Progress<'a> {
+    table_name: &'a str,
+    started: Instant,
+    reported: Instant,
+    films: Counts,
+    index_items: Counts,
+    errors: Vec<Error>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Counts {
+    written: usize,
+    failed: usize,
+}
+
+impl<'a> Progress<'a> {
+    fn new(table_name: &'a str) -> Self {
+        let now = Instant::now();
+        Progress {
+            table_name,
+            started: now,
+            reported: now,
+            films: Counts::default(),
+            index_items: Counts::default(),
+            errors: Vec::new(),
+        }
+    }
+
+    // Records a batch written to `table`, either the films table or its
+    // index table.
+    fn record(&mut self, table: &str, count: usize, result: Result<(), Error>) {
+        let counts = match table == self.table_name {
+            true => &mut self.films,
+            false => &mut self.index_items,
+        };
+        match result {
+            Ok(()) => counts.written += count,
+            Err(e) => {
+                warn!("Failed to load {count} items into {table}: {e}");
+                counts.failed += count;
+                self.errors.push(e);
+            }
+        }
+    }
+
+    fn report_every(&mut self, interval: Duration) {
+        if self.reported.elapsed() >= interval {
+            self.reported = Instant::now();
+            info!(
+                "Loading {}: {} films written ({:.0}/s), {} failed; {} index items written, {} failed",
+                self.table_name,
+                self.films.written,
+                self.rate(),
+                self.films.failed,
+                self.index_items.written,
+                self.index_items.failed
+            );
+        }
+    }
+
+    // Films written per second.
+    fn rate(&self) -> f64 {
+        self.films.written as f64 / self.started.elapsed().as_secs_f64().max(f64::EPSILON)
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        info!(
+            "Loaded {} films into {} in {:.1?} ({:.0}/s), {} failed; {} index items written, {} failed",
+            self.films.written,
+            self.table_name,
+            self.started.elapsed(),
+            self.rate(),
+            self.films.failed,
+            self.index_items.written,
+            self.index_items.failed
+        );
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::BulkLoad {
+                failed: self.films.failed + self.index_items.failed,
+                errors: self.errors,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use aws_sdk_dynamodb::{
+        types::{AttributeValue, PutRequest, WriteRequest},
+        Client,
+    };
+    use serde_json::{json, Value};
+
+    use super::{batches, Batch, Counts, Progress};
+    use crate::{
+        ddb::{backoff, error::Error, fake, write_batch},
+        models::Film,
+    };
+
+    // A DynamoDB stand-in that answers the first requests with `responses`
+    // and then reports every item as written.
+    fn client(responses: Vec<(u16, Value)>) -> (Client, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let seen = requests.clone();
+        let client = fake::client(move |_, _| {
+            let n = seen.fetch_add(1, Ordering::SeqCst);
+            responses.get(n).cloned().unwrap_or((200, json!({})))
+        });
+        (client, requests)
+    }
+
+    fn ops() -> Vec<WriteRequest> {
+        vec![WriteRequest::builder()
+            .put_request(
+                PutRequest::builder()
+                    .item("year", AttributeValue::N("1995".into()))
+                    .item("title", AttributeValue::S("Heat".into()))
+                    .build(),
+            )
+            .build()]
+    }
+
+    #[tokio::test]
+    async fn test_write_batch_retries_unprocessed_and_throttled() {
+        let unprocessed = json!({"UnprocessedItems": {"films": [
+            {"PutRequest": {"Item": {"year": {"N": "1995"}, "title": {"S": "Heat"}}}}
+        ]}});
+        let throttled = fake::error("ProvisionedThroughputExceededException", "slow down");
+        let (client, requests) = client(vec![(200, unprocessed), (400, throttled)]);
+
+        write_batch(&client, "films", &ops()).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_write_batch_fails_on_other_errors() {
+        let missing = fake::error("ResourceNotFoundException", "no table");
+        let (client, requests) = client(vec![(400, missing)]);
+
+        assert!(write_batch(&client, "films", &ops()).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        for attempt in 0..40 {
+            assert!(backoff(attempt) <= std::time::Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn test_batches() {
+        let mut film = Film::new(1995, "Heat".into());
+        film.cast = (0..30).map(|n| format!("Actor {n}")).collect();
+        let batches = batches("films", "films-index", &[film]);
+
+        let sizes = batches
+            .iter()
+            .map(|batch| match batch {
+                Batch::Write(table, ops) => (*table, ops.len()),
+                Batch::Failed(..) => panic!("unexpected failure"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sizes,
+            [("films", 1), ("films-index", 25), ("films-index", 5)]
+        );
+    }
+
+    #[test]
+    fn test_progress_aggregates_failures() {
+        let mut progress = Progress::new("films");
+        progress.record("films", 25, Ok(()));
+        progress.record("films", 3, Err(Error::throttled("films", 3)));
+        progress.record("films-index", 50, Ok(()));
+        progress.record("films-index", 25, Err(Error::throttled("films-index", 25)));
+        assert_eq!(
+            progress.films,
+            Counts {
+                written: 25,
+                failed: 3
+            }
+        );
+        assert_eq!(
+            progress.index_items,
+            Counts {
+                written: 50,
+                failed: 25
+            }
+        );
+
+        match progress.finish() {
+            Err(Error::BulkLoad { failed, errors }) => {
+                assert_eq!(failed, 28);
+                assert_eq!(errors.len(), 2);
+            }
+            other => panic!("expected a bulk load error, got {other:?}"),
+        }
+    }
+}
@@ -1,46 +1,230 @@
-use crate::models::Film;
+use crate::{
+    dataset::Dataset,
+    models::{normalize, Film},
+};
 use aws_sdk_dynamodb::{
-    operation::create_table::builders::CreateTableFluentBuilder,
+    error::{ProvideErrorMetadata, SdkError},
+    operation::{
+        batch_write_item::BatchWriteItemError, create_table::builders::CreateTableFluentBuilder,
+    },
     types::{
-        AttributeDefinition, KeySchemaElement, KeyType, ProvisionedThroughput, ScalarAttributeType,
-        TableStatus, WriteRequest,
+        AttributeDefinition, AttributeValue, DeleteRequest, GlobalSecondaryIndex, KeySchemaElement,
+        KeyType, Projection, ProjectionType, ProvisionedThroughput, PutRequest,
+        ScalarAttributeType, TableDescription, WriteRequest,
     },
     Client,
 };
-use futures::future::join_all;
-use std::{collections::HashMap, time::Duration};
+use rand::Rng;
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Duration,
+};
+use tokio_stream::StreamExt;
 use tracing::{debug, info, trace};
-mod error;
-const CAPACITY: i64 = 10;
+pub mod error;
+#[cfg(test)]
+pub(crate) mod fake;
+mod load;
+pub mod scan;
+pub mod wait;
+pub use load::{bulk_load_data, DEFAULT_LOAD_CONCURRENCY};
+use wait::{readiness, wait_until, Check, Wait};
+// Read and write capacity units for new tables, unless configured.
+pub const CAPACITY: i64 = 10;
+
+/// The index table has one item per (film, term) pair, e.g. a film and one
+/// of its genres or cast members, and a GSI to look films up by term.
+pub const TERM_INDEX: &str = "by-term";
+
+pub fn index_table_name(table_name: &str) -> String {
+    format!("{table_name}-index")
+}
+
+/// A key attribute's name, its place in the key and its type.
+type KeyAttribute = (&'static str, KeyType, ScalarAttributeType);
 
+fn films_keys() -> [KeyAttribute; 2] {
+    [
+        ("year", KeyType::Hash, ScalarAttributeType::N),
+        ("title", KeyType::Range, ScalarAttributeType::S),
+    ]
+}
+
+fn index_keys() -> [KeyAttribute; 2] {
+    [
+        ("film", KeyType::Hash, ScalarAttributeType::S),
+        ("term", KeyType::Range, ScalarAttributeType::S),
+    ]
+}
+
+fn term_index_keys() -> [KeyAttribute; 2] {
+    [
+        ("term", KeyType::Hash, ScalarAttributeType::S),
+        ("film", KeyType::Range, ScalarAttributeType::S),
+    ]
+}
+
+/// Creates the films and index tables if they're missing, loading the films
+/// dataset into a new films table from `seed` when it's given, with up to
+/// `load_concurrency` batch writes at once. New tables are waited on with
+/// `wait`.
 #[tracing::instrument(level = "trace")]
-pub async fn initialize(client: &Client, table_name: &str) -> Result<(), error::Error> {
+pub async fn initialize(
+    client: &Client,
+    table_name: &str,
+    capacity: i64,
+    seed: Option<&Dataset>,
+    load_concurrency: usize,
+    wait: &Wait,
+) -> Result<(), error::Error> {
     info!("Initializing Films DynamoDB in {table_name}");
+    let index_table = index_table_name(table_name);
 
     if table_exists(client, table_name).await? {
         info!("Found existing table {table_name}. Not attempting to bulk load data");
+        if !table_exists(client, &index_table).await? {
+            info!("Index table does not exist, creating {index_table}");
+            create_index_table(client, &index_table, capacity)
+                .send()
+                .await?;
+            await_table(client, &index_table, wait).await?;
+            backfill_index(client, table_name).await?;
+        }
     } else {
         info!("Table does not exist, creating {table_name}");
-        create_table(client, table_name, "year", "title", CAPACITY)
+        let [(hash_key, ..), (range_key, ..)] = films_keys();
+        create_table(client, table_name, hash_key, range_key, capacity)
             .send()
             .await?;
-        await_table(client, table_name).await?;
-        bulk_load_data(client, table_name).await?
+        if !table_exists(client, &index_table).await? {
+            create_index_table(client, &index_table, capacity)
+                .send()
+                .await?;
+        }
+        await_table(client, table_name, wait).await?;
+        await_table(client, &index_table, wait).await?;
+        if let Some(dataset) = seed {
+            bulk_load_data(client, table_name, dataset, load_concurrency).await?
+        }
     }
 
     Ok(())
 }
 
+/// Deletes the films and index tables, and every film in them, waiting until
+/// they're gone so they can be created again.
+#[tracing::instrument(level = "trace")]
+pub async fn drop_tables(
+    client: &Client,
+    table_name: &str,
+    wait: &Wait,
+) -> Result<(), error::Error> {
+    for table in [table_name.to_string(), index_table_name(table_name)] {
+        if table_exists(client, &table).await? {
+            info!("Deleting table {table}");
+            client.delete_table().table_name(&table).send().await?;
+            await_table_deleted(client, &table, wait).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the films and index tables exist and have the key schema
+/// `initialize` would create them with, without changing anything.
+#[tracing::instrument(level = "trace")]
+pub async fn verify(client: &Client, table_name: &str) -> Result<(), error::Error> {
+    info!("Verifying Films DynamoDB in {table_name}");
+    let films = describe_table(client, table_name).await?;
+    check_keys(
+        table_name,
+        films.key_schema(),
+        films.attribute_definitions(),
+        &films_keys(),
+    )?;
+
+    let index_table = index_table_name(table_name);
+    let index = describe_table(client, &index_table).await?;
+    check_keys(
+        &index_table,
+        index.key_schema(),
+        index.attribute_definitions(),
+        &index_keys(),
+    )?;
+    let term_index = index
+        .global_secondary_indexes()
+        .unwrap_or_default()
+        .iter()
+        .find(|gsi| gsi.index_name() == Some(TERM_INDEX))
+        .ok_or_else(|| {
+            error::Error::schema_mismatch(&index_table, format!("no {TERM_INDEX} index"))
+        })?;
+    check_keys(
+        &format!("{index_table}/{TERM_INDEX}"),
+        term_index.key_schema(),
+        index.attribute_definitions(),
+        &term_index_keys(),
+    )
+}
+
+async fn describe_table(
+    client: &Client,
+    table_name: &str,
+) -> Result<TableDescription, error::Error> {
+    describe(client, table_name)
+        .await?
+        .ok_or_else(|| error::Error::table_missing(table_name))
+}
+
+// Compares a table's or index's key schema with `expected`, looking up each
+// key's type in the table's attribute definitions.
+fn check_keys(
+    table_name: &str,
+    key_schema: Option<&[KeySchemaElement]>,
+    definitions: Option<&[AttributeDefinition]>,
+    expected: &[KeyAttribute],
+) -> Result<(), error::Error> {
+    let key_schema = key_schema.unwrap_or_default();
+    if key_schema.len() != expected.len() {
+        return Err(error::Error::schema_mismatch(
+            table_name,
+            format!(
+                "expected {} key attributes, found {}",
+                expected.len(),
+                key_schema.len()
+            ),
+        ));
+    }
+    for (name, key_type, attribute_type) in expected {
+        let key = key_schema
+            .iter()
+            .find(|key| key.attribute_name() == Some(*name))
+            .ok_or_else(|| error::Error::schema_mismatch(table_name, format!("no key {name}")))?;
+        if key.key_type() != Some(key_type) {
+            return Err(error::Error::schema_mismatch(
+                table_name,
+                format!("expected {name} to be a {} key", key_type.as_str()),
+            ));
+        }
+        let defined = definitions
+            .unwrap_or_default()
+            .iter()
+            .find(|definition| definition.attribute_name() == Some(*name))
+            .and_then(|definition| definition.attribute_type());
+        if defined != Some(attribute_type) {
+            return Err(error::Error::schema_mismatch(
+                table_name,
+                format!("expected {name} to have type {}", attribute_type.as_str()),
+            ));
+        }
+    }
+    Ok(())
+}
+
 #[tracing::instrument(level = "trace")]
 // Does table exist?
 pub async fn table_exists(client: &Client, table: &str) -> Result<bool, error::Error> {
     debug!("Checking for table: {table}");
-    let table_list = client.list_tables().send().await;
-
-    match table_list {
-        Ok(list) => Ok(list.table_names().as_ref().unwrap().contains(&table.into())),
-        Err(e) => Err(e.into()),
-    }
+    Ok(describe(client, table).await?.is_some())
 }
 
 #[tracing::instrument(level = "trace")]
@@ -87,63 +271,210 @@ pub fn create_table(
         )
 }
 
-const TABLE_WAIT_POLLS: u64 = 6;
-const TABLE_WAIT_TIMEOUT: u64 = 10;
-pub async fn await_table(client: &Client, table_name: &str) -> Result<(), error::Error> {
-    // TODO: Use an adaptive backoff retry, rather than a sleeping loop.
-    for _ in 0..TABLE_WAIT_POLLS {
-        debug!("Checking if table is ready: {table_name}");
-        if let Some(table) = client
-            .describe_table()
-            .table_name(table_name)
-            .send()
-            .await?
-            .table()
-        {
-            if matches!(table.table_status, Some(TableStatus::Active)) {
-                debug!("Table is ready");
-                return Ok(());
-            } else {
-                debug!("Table is NOT ready")
-            }
-        }
-        tokio::time::sleep(Duration::from_secs(TABLE_WAIT_TIMEOUT)).await;
-    }
+#[tracing::instrument(level = "trace")]
+pub fn create_index_table(
+    client: &Client,
+    table_name: &str,
+    capacity: i64,
+) -> CreateTableFluentBuilder {
+    info!("Creating index table: {table_name} with capacity {capacity}");
+    let throughput = ProvisionedThroughput::builder()
+        .read_capacity_units(capacity)
+        .write_capacity_units(capacity)
+        .build();
+    client
+        .create_table()
+        .table_name(table_name)
+        .key_schema(
+            KeySchemaElement::builder()
+                .attribute_name("film")
+                .key_type(KeyType::Hash)
+                .build(),
+        )
+        .key_schema(
+            KeySchemaElement::builder()
+                .attribute_name("term")
+                .key_type(KeyType::Range)
+                .build(),
+        )
+        .attribute_definitions(
+            AttributeDefinition::builder()
+                .attribute_name("film")
+                .attribute_type(ScalarAttributeType::S)
+                .build(),
+        )
+        .attribute_definitions(
+            AttributeDefinition::builder()
+                .attribute_name("term")
+                .attribute_type(ScalarAttributeType::S)
+                .build(),
+        )
+        .global_secondary_indexes(
+            GlobalSecondaryIndex::builder()
+                .index_name(TERM_INDEX)
+                .key_schema(
+                    KeySchemaElement::builder()
+                        .attribute_name("term")
+                        .key_type(KeyType::Hash)
+                        .build(),
+                )
+                .key_schema(
+                    KeySchemaElement::builder()
+                        .attribute_name("film")
+                        .key_type(KeyType::Range)
+                        .build(),
+                )
+                .projection(
+                    Projection::builder()
+                        .projection_type(ProjectionType::All)
+                        .build(),
+                )
+                .provisioned_throughput(throughput.clone())
+                .build(),
+        )
+        .provisioned_throughput(throughput)
+}
 
-    Err(error::Error::table_not_ready(table_name))
+/// The index's key for a film. Years are zero padded so that films sort by
+/// year and then title, and a year can be matched with `begins_with`.
+pub fn film_key(year: i32, title: &str) -> String {
+    format!("{}{title}", year_prefix(year))
 }
 
-// Must be less than 26.
-const CHUNK_SIZE: usize = 25;
+pub fn year_prefix(year: i32) -> String {
+    format!("{year:04}#")
+}
 
-pub async fn bulk_load_data(client: &Client, table_name: &str) -> Result<(), error::Error> {
-    debug!("Loading data into table {table_name}");
-    let data: Vec<Film> =
-        serde_json::from_str(include_str!("./t.json")).expect("loading large Films dataset");
+pub fn genre_term(genre: &str) -> String {
+    format!("genre#{}", normalize(genre))
+}
 
-    let data_size = data.len();
-    trace!("Loading {data_size} items in batches of {CHUNK_SIZE}");
+pub fn actor_term(actor: &str) -> String {
+    format!("actor#{}", normalize(actor))
+}
 
-    let ops = data
+/// Every term a film should be indexed under.
+pub fn index_terms(film: &Film) -> BTreeSet<String> {
+    film.genres
         .iter()
-        .map(|v| {
-            WriteRequest::builder()
-                .set_put_request(Some(v.into()))
-                .build()
-        })
-        .collect::<Vec<WriteRequest>>();
+        .map(|genre| genre_term(genre))
+        .chain(film.cast.iter().map(|actor| actor_term(actor)))
+        .collect()
+}
+
+pub fn index_put(film: &Film, term: &str) -> WriteRequest {
+    WriteRequest::builder()
+        .put_request(
+            PutRequest::builder()
+                .item("film", AttributeValue::S(film_key(film.year, &film.title)))
+                .item("term", AttributeValue::S(term.into()))
+                .item("year", AttributeValue::N(film.year.to_string()))
+                .item("title", AttributeValue::S(film.title.clone()))
+                .item(
+                    "title_normalized",
+                    AttributeValue::S(normalize(&film.title)),
+                )
+                .build(),
+        )
+        .build()
+}
 
-    let batches = ops
-        .chunks(CHUNK_SIZE)
-        .map(|chunk| write_batch(client, table_name, chunk));
-    let batches_count = batches.len();
+pub fn index_delete(year: i32, title: &str, term: &str) -> WriteRequest {
+    WriteRequest::builder()
+        .delete_request(
+            DeleteRequest::builder()
+                .key("film", AttributeValue::S(film_key(year, title)))
+                .key("term", AttributeValue::S(term.into()))
+                .build(),
+        )
+        .build()
+}
 
-    trace!("Awaiting batches, count: {batches_count}");
-    join_all(batches).await;
+// Index every film in `table_name`, for tables created before the index.
+async fn backfill_index(client: &Client, table_name: &str) -> Result<(), error::Error> {
+    info!("Backfilling index for {table_name}");
+    let index_table = index_table_name(table_name);
+    let mut items = scan::parallel_scan(client, table_name, scan::DEFAULT_SCAN_SEGMENTS);
+    let mut ops = Vec::with_capacity(CHUNK_SIZE);
+    while let Some(item) = items.next().await {
+        let film = Film::try_from(&item?).map_err(error::Error::unhandled)?;
+        for term in index_terms(&film) {
+            ops.push(index_put(&film, &term));
+            if ops.len() == CHUNK_SIZE {
+                write_batch(client, &index_table, &ops).await?;
+                ops.clear();
+            }
+        }
+    }
+    if !ops.is_empty() {
+        write_batch(client, &index_table, &ops).await?;
+    }
+    Ok(())
+}
 
+/// Waits until `table_name` and its GSIs are active, including any GSI
+/// backfill.
+pub async fn await_table(
+    client: &Client,
+    table_name: &str,
+    wait: &Wait,
+) -> Result<(), error::Error> {
+    wait_until(wait, table_name, || async {
+        Ok(match describe(client, table_name).await? {
+            Some(table) => readiness(&table),
+            None => Check::Pending("table does not exist yet".into()),
+        })
+    })
+    .await?;
+    debug!("Table is ready: {table_name}");
+    Ok(())
+}
+
+async fn await_table_deleted(
+    client: &Client,
+    table_name: &str,
+    wait: &Wait,
+) -> Result<(), error::Error> {
+    wait_until(wait, table_name, || async {
+        Ok(match describe(client, table_name).await? {
+            Some(table) => Check::Pending(format!(
+                "table is {}",
+                table
+                    .table_status()
+                    .map(|s| s.as_str())
+                    .unwrap_or("unknown")
+            )),
+            None => Check::Ready(()),
+        })
+    })
+    .await?;
+    debug!("Table is deleted: {table_name}");
     Ok(())
 }
 
+// Describes `table_name`, or returns None if there's no such table.
+async fn describe(
+    client: &Client,
+    table_name: &str,
+) -> Result<Option<TableDescription>, error::Error> {
+    match client.describe_table().table_name(table_name).send().await {
+        Ok(output) => Ok(output.table),
+        Err(SdkError::ServiceError(e)) if e.err().is_resource_not_found_exception() => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Must be less than 26.
+pub const CHUNK_SIZE: usize = 25;
+
+/// Attempts at writing or reading a batch before giving up on its
+/// unprocessed items.
+pub const BATCH_ATTEMPTS: u32 = 10;
+const BACKOFF_BASE: Duration = Duration::from_millis(50);
+const BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+/// Writes `ops` to `table_name`, retrying unprocessed items and throttled
+/// requests with exponential backoff.
 pub async fn write_batch(
     client: &Client,
     table_name: &str,
@@ -154,18 +485,53 @@ pub async fn write_batch(
         "Cannot write more than 25 items in a batch"
     );
     let mut unprocessed = Some(HashMap::from([(table_name.to_string(), ops.to_vec())]));
-    while unprocessed_count(unprocessed.as_ref(), table_name) > 0 {
+    for attempt in 0..BATCH_ATTEMPTS {
         let count = unprocessed_count(unprocessed.as_ref(), table_name);
+        if count == 0 {
+            return Ok(());
+        }
+        if attempt > 0 {
+            let delay = backoff(attempt);
+            debug!("Retrying {count} unprocessed items in {table_name} after {delay:?}");
+            tokio::time::sleep(delay).await;
+        }
         trace!("Adding {count} unprocessed items");
-        unprocessed = client
+        match client
             .batch_write_item()
-            .set_request_items(unprocessed)
+            .set_request_items(unprocessed.clone())
             .send()
-            .await?
-            .unprocessed_items;
+            .await
+        {
+            Ok(output) => unprocessed = output.unprocessed_items,
+            Err(e) if is_throttled(&e) => debug!("Writes to {table_name} were throttled"),
+            Err(e) => return Err(e.into()),
+        }
     }
 
-    Ok(())
+    match unprocessed_count(unprocessed.as_ref(), table_name) {
+        0 => Ok(()),
+        count => Err(error::Error::throttled(table_name, count)),
+    }
+}
+
+/// A random delay of up to `BACKOFF_BASE * 2^attempt`, capped at
+/// `BACKOFF_MAX`, so that retrying writers spread out ("full jitter").
+pub fn backoff(attempt: u32) -> Duration {
+    let ceiling = BACKOFF_BASE
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(BACKOFF_MAX);
+    rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+}
+
+fn is_throttled(error: &SdkError<BatchWriteItemError>) -> bool {
+    match error {
+        SdkError::ServiceError(e) => {
+            e.err().is_provisioned_throughput_exceeded_exception()
+                || e.err().is_request_limit_exceeded()
+                || e.err().code() == Some("ThrottlingException")
+        }
+        _ => false,
+    }
 }
 
 fn unprocessed_count(
@@ -176,3 +542,64 @@ fn unprocessed_count(
         .map(|m| m.get(table_name).map(|v| v.len()).unwrap_or_default())
         .unwrap_or_default()
 }
+
+#[cfg(test)]
+mod test {
+    use aws_sdk_dynamodb::types::{
+        AttributeDefinition, KeySchemaElement, KeyType, ScalarAttributeType,
+    };
+
+    use serde_json::json;
+
+    use super::{check_keys, error::Error, fake, films_keys, table_exists};
+
+    fn key(name: &str, key_type: KeyType) -> KeySchemaElement {
+        KeySchemaElement::builder()
+            .attribute_name(name)
+            .key_type(key_type)
+            .build()
+    }
+
+    fn definition(name: &str, attribute_type: ScalarAttributeType) -> AttributeDefinition {
+        AttributeDefinition::builder()
+            .attribute_name(name)
+            .attribute_type(attribute_type)
+            .build()
+    }
+
+    #[test]
+    fn test_check_keys() {
+        let keys = [key("year", KeyType::Hash), key("title", KeyType::Range)];
+        let definitions = [
+            definition("year", ScalarAttributeType::N),
+            definition("title", ScalarAttributeType::S),
+        ];
+        assert!(check_keys("films", Some(&keys), Some(&definitions), &films_keys()).is_ok());
+
+        let swapped = [key("year", KeyType::Range), key("title", KeyType::Hash)];
+        assert!(matches!(
+            check_keys("films", Some(&swapped), Some(&definitions), &films_keys()),
+            Err(Error::SchemaMismatch(..))
+        ));
+
+        let string_year = [
+            definition("year", ScalarAttributeType::S),
+            definition("title", ScalarAttributeType::S),
+        ];
+        assert!(check_keys("films", Some(&keys), Some(&string_year), &films_keys()).is_err());
+        assert!(check_keys("films", Some(&keys[..1]), Some(&definitions), &films_keys()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_table_exists() {
+        let client = fake::client(|_, body| match body["TableName"].as_str() {
+            Some("films") => (200, json!({"Table": {"TableName": "films"}})),
+            _ => (
+                400,
+                fake::error("ResourceNotFoundException", "Requested resource not found"),
+            ),
+        });
+        assert!(table_exists(&client, "films").await.unwrap());
+        assert!(!table_exists(&client, "missing").await.unwrap());
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use aws_sdk_dynamodb::{types::AttributeValue, Client};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+
+use super::error::Error;
+
+/// How many segments a full-table scan reads at once, unless configured.
+pub const DEFAULT_SCAN_SEGMENTS: u32 = 4;
+
+// DynamoDB rejects a TotalSegments above this.
+const MAX_SCAN_SEGMENTS: u32 = 1_000_000;
+
+/// Clamps a configured segment count to what DynamoDB accepts.
+pub fn scan_segments(segments: u32) -> i32 {
+    segments.clamp(1, MAX_SCAN_SEGMENTS) as i32
+}
+
+/// Reads every item in `table_name` as a parallel scan of `segments`
+/// segments, merged into one stream as items arrive, in no particular order.
+///
+/// Each segment's paginator only fetches its next page once the stream is
+/// polled for more, so a slow consumer pauses the scan rather than having
+/// the table buffered in memory.
+pub fn parallel_scan(
+    client: &Client,
+    table_name: &str,
+    segments: u32,
+) -> BoxStream<'static, Result<HashMap<String, AttributeValue>, Error>> {
+    let total = scan_segments(segments);
+    let segments = (0..total).map(|segment| {
+        client
+            .scan()
+            .table_name(table_name)
+            .segment(segment)
+            .total_segments(total)
+            .into_paginator()
+            .items()
+            .send()
+    });
+    stream::select_all(segments)
+        .map(|item| item.map_err(Error::from))
+        .boxed()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::sync::{Arc, Mutex};
+
+    use aws_sdk_dynamodb::Client;
+    use futures::TryStreamExt;
+    use serde_json::{json, Value};
+
+    use super::parallel_scan;
+    use crate::{ddb::fake, models::Film};
+
+    /// The segment and total segments of each Scan request.
+    pub(crate) type ScanLog = Arc<Mutex<Vec<(i64, i64)>>>;
+
+    /// A DynamoDB stand-in holding `films` that answers Scan requests two
+    /// items at a time, putting every `TotalSegments`th film in the same
+    /// segment.
+    pub(crate) fn scan_client(films: &[(i32, &str)]) -> (Client, ScanLog) {
+        let items: Vec<Value> = films
+            .iter()
+            .map(|(year, title)| json!({"year": {"N": year.to_string()}, "title": {"S": title}}))
+            .collect();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let requests = log.clone();
+        let client = fake::client(move |_, body| {
+            let segment = body["Segment"].as_i64().unwrap_or(0);
+            let total = body["TotalSegments"].as_i64().unwrap_or(1);
+            requests.lock().unwrap().push((segment, total));
+
+            let in_segment: Vec<&Value> = items
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i as i64 % total == segment)
+                .map(|(_, item)| item)
+                .collect();
+            let start = match body.get("ExclusiveStartKey") {
+                Some(key) => in_segment.iter().position(|item| *item == key).unwrap() + 1,
+                None => 0,
+            };
+            let limit = body["Limit"].as_u64().unwrap_or(2).min(2) as usize;
+            let page: Vec<&Value> = in_segment.iter().skip(start).take(limit).copied().collect();
+            let mut output = json!({ "Items": page });
+            if start + page.len() < in_segment.len() {
+                output["LastEvaluatedKey"] = (*page.last().unwrap()).clone();
+            }
+            (200, output)
+        });
+        (client, log)
+    }
+
+    pub(crate) const FILMS: &[(i32, &str)] = &[
+        (1995, "Heat"),
+        (1998, "Ronin"),
+        (2004, "Collateral"),
+        (2019, "Knives Out"),
+        (1972, "The Godfather"),
+        (1982, "Blade Runner"),
+        (1986, "Aliens"),
+    ];
+
+    #[tokio::test]
+    async fn test_parallel_scan_reads_every_segment() {
+        let (client, log) = scan_client(FILMS);
+        let items: Vec<_> = parallel_scan(&client, "films", 3)
+            .try_collect()
+            .await
+            .unwrap();
+
+        let mut titles: Vec<String> = items
+            .iter()
+            .map(|item| Film::try_from(item).unwrap().title)
+            .collect();
+        titles.sort();
+        let mut expected: Vec<&str> = FILMS.iter().map(|(_, title)| *title).collect();
+        expected.sort();
+        assert_eq!(titles, expected);
+
+        let mut segments: Vec<_> = log.lock().unwrap().clone();
+        segments.sort();
+        segments.dedup();
+        assert_eq!(segments, [(0, 3), (1, 3), (2, 3)]);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use aws_sdk_dynamodb::types::{IndexStatus, TableDescription, TableStatus};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use super::error::Error;
+
+/// How long to keep polling something, and when to give up.
+#[derive(Clone, Debug)]
+pub struct Wait {
+    /// Gives up once this much time has passed since the first poll.
+    pub deadline: Duration,
+    /// The delay after the first poll, doubling after each poll after that.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Stops waiting early, e.g. when the API is interrupted at startup.
+    pub cancel: CancellationToken,
+}
+
+impl Wait {
+    pub fn new(deadline: Duration, cancel: CancellationToken) -> Self {
+        Wait {
+            deadline,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            cancel,
+        }
+    }
+}
+
+impl Default for Wait {
+    fn default() -> Self {
+        Wait::new(Duration::from_secs(300), CancellationToken::new())
+    }
+}
+
+/// What a poll found: either the thing waited for, or a description of
+/// where it's got to so far.
+pub enum Check<T> {
+    Ready(T),
+    Pending(String),
+}
+
+/// Polls `check` with exponential backoff until it's ready. Fails with
+/// `Error::TableNotReady`, carrying the last status `check` reported, if
+/// `wait.deadline` passes first, or `Error::Cancelled` if `wait.cancel` is
+/// cancelled.
+pub async fn wait_until<T, F, Fut>(wait: &Wait, table_name: &str, mut check: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Check<T>, Error>>,
+{
+    let started = Instant::now();
+    let mut delay = wait.initial_delay;
+    loop {
+        let status = match check().await? {
+            Check::Ready(value) => return Ok(value),
+            Check::Pending(status) => status,
+        };
+        debug!("Waiting for {table_name}: {status}");
+
+        let remaining = wait.deadline.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return Err(Error::table_not_ready(table_name, status));
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(delay.min(remaining)) => {}
+            _ = wait.cancel.cancelled() => return Err(Error::Cancelled(table_name.into())),
+        }
+        delay = (delay * 2).min(wait.max_delay);
+    }
+}
+
+/// Whether a table and every one of its GSIs are active and done
+/// backfilling, so it can take reads and writes.
+pub fn readiness(table: &TableDescription) -> Check<()> {
+    match table.table_status() {
+        Some(TableStatus::Active) => {}
+        status => {
+            let status = status.map(|s| s.as_str()).unwrap_or("unknown");
+            return Check::Pending(format!("table is {status}"));
+        }
+    }
+    for gsi in table.global_secondary_indexes().unwrap_or_default() {
+        let name = gsi.index_name().unwrap_or_default();
+        match gsi.index_status() {
+            Some(IndexStatus::Active) if gsi.backfilling() != Some(true) => {}
+            Some(IndexStatus::Active) => {
+                return Check::Pending(format!("index {name} is backfilling"))
+            }
+            status => {
+                let status = status.map(|s| s.as_str()).unwrap_or("unknown");
+                return Check::Pending(format!("index {name} is {status}"));
+            }
+        }
+    }
+    Check::Ready(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use aws_sdk_dynamodb::types::{
+        GlobalSecondaryIndexDescription, IndexStatus, TableDescription, TableStatus,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    use super::{readiness, wait_until, Check, Wait};
+    use crate::ddb::error::Error;
+
+    fn wait(deadline: Duration) -> Wait {
+        Wait {
+            initial_delay: Duration::from_millis(1),
+            ..Wait::new(deadline, CancellationToken::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_ready() {
+        let polls = AtomicUsize::new(0);
+        let result = wait_until(&wait(Duration::from_secs(5)), "films", || async {
+            match polls.fetch_add(1, Ordering::SeqCst) {
+                0..=2 => Ok(Check::Pending("table is CREATING".into())),
+                n => Ok(Check::Ready(n)),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_deadline_keeps_last_status() {
+        let result = wait_until(&wait(Duration::from_millis(20)), "films", || async {
+            Ok::<Check<()>, Error>(Check::Pending("table is CREATING".into()))
+        })
+        .await;
+        match result {
+            Err(Error::TableNotReady(table, status)) => {
+                assert_eq!(table, "films");
+                assert_eq!(status, "table is CREATING");
+            }
+            other => panic!("expected TableNotReady, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_cancelled() {
+        let wait = wait(Duration::from_secs(60));
+        wait.cancel.cancel();
+        let result = wait_until(&wait, "films", || async {
+            Ok::<Check<()>, Error>(Check::Pending("table is CREATING".into()))
+        })
+        .await;
+        assert!(matches!(result, Err(Error::Cancelled(_))));
+    }
+
+    #[test]
+    fn test_readiness() {
+        let gsi = |status, backfilling| {
+            GlobalSecondaryIndexDescription::builder()
+                .index_name("by-term")
+                .index_status(status)
+                .backfilling(backfilling)
+                .build()
+        };
+        let table = |status, gsi| {
+            TableDescription::builder()
+                .table_status(status)
+                .global_secondary_indexes(gsi)
+                .build()
+        };
+
+        let ready = table(TableStatus::Active, gsi(IndexStatus::Active, false));
+        assert!(matches!(readiness(&ready), Check::Ready(())));
+
+        let creating = table(TableStatus::Creating, gsi(IndexStatus::Creating, false));
+        assert!(matches!(readiness(&creating), Check::Pending(s) if s == "table is CREATING"));
+
+        let backfilling = table(TableStatus::Active, gsi(IndexStatus::Active, true));
+        assert!(
+            matches!(readiness(&backfilling), Check::Pending(s) if s == "index by-term is backfilling")
+        );
+    }
+}
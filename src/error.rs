@@ -0,0 +1,147 @@
+use std::convert::Infallible;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use warp::{
+    body::BodyDeserializeError,
+    http::{header, StatusCode},
+    reject::{
+        InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader,
+        PayloadTooLarge, Reject, UnsupportedMediaType,
+    },
+    Rejection, Reply,
+};
+
+use crate::{ddb, models::FilmError, validation::FieldError};
+
+/// Every error a handler can fail with. Handlers reject with it and
+/// `handle_rejection` renders it as a problem+json response.
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("the film has {} invalid fields", .0.len())]
+    Invalid(Vec<FieldError>),
+
+    #[error("the request body is larger than {0} bytes")]
+    TooLarge(u64),
+
+    #[error("film not found: {1} ({0})")]
+    NotFound(i32, String),
+
+    #[error("none of the accepted types are available: {0}; use application/json, application/x-ndjson or text/csv")]
+    NotAcceptable(String),
+
+    #[error(transparent)]
+    Film(#[from] FilmError),
+
+    #[error(transparent)]
+    Table(#[from] ddb::error::Error),
+}
+
+impl Reject for ApiError {}
+
+impl From<FilmError> for Rejection {
+    fn from(err: FilmError) -> Self {
+        ApiError::from(err).into()
+    }
+}
+
+impl ApiError {
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        ApiError::BadRequest(detail.into())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::NotFound(..) => StatusCode::NOT_FOUND,
+            ApiError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            ApiError::Film(FilmError::AlreadyExists(..)) => StatusCode::CONFLICT,
+            ApiError::Film(FilmError::InvalidCursor(_)) => StatusCode::BAD_REQUEST,
+            ApiError::Film(FilmError::Table(ddb::error::Error::TableNotReady(..)))
+            | ApiError::Table(ddb::error::Error::TableNotReady(..)) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ApiError::Film(_) | ApiError::Table(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// An RFC 7807 problem details body.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Every invalid field, for 422 responses.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Problem {
+            problem_type: "about:blank".into(),
+            title: status.canonical_reason().unwrap_or("Error").into(),
+            status: status.as_u16(),
+            detail: detail.into(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl Reply for Problem {
+    fn into_response(self) -> warp::reply::Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let reply = warp::reply::with_status(warp::reply::json(&self), status);
+        warp::reply::with_header(reply, header::CONTENT_TYPE, "application/problem+json")
+            .into_response()
+    }
+}
+
+/// Renders rejections, ours and warp's, as problem+json.
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let problem = if err.is_not_found() {
+        Problem::new(StatusCode::NOT_FOUND, "no route matches this path")
+    } else if let Some(e) = err.find::<ApiError>() {
+        let status = e.status();
+        if status.is_server_error() {
+            // Don't leak DynamoDB internals to clients.
+            log::warn!("Error! {}", e);
+            Problem::new(status, "the films table could not be read or written")
+        } else if let ApiError::Invalid(errors) = e {
+            Problem {
+                errors: errors.clone(),
+                ..Problem::new(status, e.to_string())
+            }
+        } else {
+            Problem::new(status, e.to_string())
+        }
+    } else if let Some(e) = err.find::<BodyDeserializeError>() {
+        Problem::new(StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = err.find::<InvalidQuery>() {
+        Problem::new(StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = err.find::<InvalidHeader>() {
+        Problem::new(StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = err.find::<MissingHeader>() {
+        Problem::new(StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = err.find::<LengthRequired>() {
+        Problem::new(StatusCode::LENGTH_REQUIRED, e.to_string())
+    } else if let Some(e) = err.find::<PayloadTooLarge>() {
+        Problem::new(StatusCode::PAYLOAD_TOO_LARGE, e.to_string())
+    } else if let Some(e) = err.find::<UnsupportedMediaType>() {
+        Problem::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string())
+    } else if let Some(e) = err.find::<MethodNotAllowed>() {
+        Problem::new(StatusCode::METHOD_NOT_ALLOWED, e.to_string())
+    } else {
+        log::warn!("Unhandled rejection: {:?}", err);
+        Problem::new(StatusCode::INTERNAL_SERVER_ERROR, "unhandled rejection")
+    };
+    Ok(problem)
+}
@@ -1,8 +1,16 @@
-use super::models::{Film, ListOptions};
+use super::models::{CreateOptions, ExportOptions, Film, GetOptions, ListOptions};
+use crate::error::{handle_rejection, ApiError};
 use crate::handlers;
+use crate::store::Store;
 
-use aws_sdk_dynamodb::Client;
-use warp::Filter;
+use futures::{Stream, TryStreamExt};
+use warp::{
+    hyper::body::{Buf, Bytes},
+    Filter,
+};
+
+/// The default limit on `POST /films/batch` bodies, 16 MiB.
+pub const DEFAULT_BATCH_LIMIT: u64 = 16 * 1024 * 1024;
 
 pub fn welcome() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     let host = warp::header::optional::<String>("host");
@@ -12,64 +20,134 @@ pub fn welcome() -> impl Filter<Extract = impl warp::Reply, Error = warp::Reject
         .and_then(handlers::welcome)
 }
 
-/// GET /films
+/// Every route, with up to `batch_limit` bytes allowed in `POST /films/batch`.
 pub fn films(
-    dbclient: Client,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    store: Store,
+    batch_limit: u64,
+) -> impl Filter<Extract = impl warp::Reply, Error = std::convert::Infallible> + Clone {
     welcome()
-        .or(films_list(dbclient.clone()))
-        .or(films_create(dbclient.clone()))
-    //     .or(films_update(dbclient.clone()))
-    //    .or(films_delete(dbclient.clone()))
+        .or(films_list(store.clone()))
+        .or(films_export(store.clone()))
+        .or(films_get(store.clone()))
+        .or(actor_films(store.clone()))
+        .or(films_create(store.clone()))
+        .or(films_import(store.clone(), batch_limit))
+        .or(films_update(store.clone()))
+        .or(films_patch(store.clone()))
+        .or(films_delete(store.clone()))
+        .recover(handle_rejection)
 }
 
-/// GET /films?offset=3&limit=5
+/// GET /films?offset=3&limit=5 as JSON, NDJSON or CSV
 pub fn films_list(
-    dbclient: Client,
+    store: Store,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("films")
         .and(warp::get())
         .and(warp::query::<ListOptions>())
-        .and(with_db(dbclient))
+        .and(warp::header::optional::<String>("accept"))
+        .and(with_store(store))
         .and_then(handlers::list_films)
 }
 
-/// POST /films with JSON body
+/// GET /films/export as NDJSON, JSON or CSV
+pub fn films_export(
+    store: Store,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("films" / "export")
+        .and(warp::get())
+        .and(warp::query::<ExportOptions>())
+        .and(warp::header::optional::<String>("accept"))
+        .and(with_store(store))
+        .and_then(handlers::export_films)
+}
+
+/// GET /actors/{name}/films as JSON, NDJSON or CSV
+pub fn actor_films(
+    store: Store,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("actors" / String / "films")
+        .and(warp::get())
+        .and(warp::query::<ListOptions>())
+        .and(warp::header::optional::<String>("accept"))
+        .and(with_store(store))
+        .and_then(handlers::list_actor_films)
+}
+
+/// GET /films/{year}/{title}?fields=title,year
+pub fn films_get(
+    store: Store,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("films" / i32 / String)
+        .and(warp::get())
+        .and(warp::query::<GetOptions>())
+        .and(with_store(store))
+        .and_then(handlers::get_film)
+}
+
+/// POST /films?upsert=true with JSON body
 pub fn films_create(
-    dbclient: Client,
+    store: Store,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("films")
         .and(warp::post())
+        .and(warp::query::<CreateOptions>())
         .and(json_body())
-        .and(with_db(dbclient))
+        .and(with_store(store))
         .and_then(handlers::create_film)
 }
 
-/// PUT /films/title with JSON body
-// pub fn films_update(
-//     dbclient: Client,
-// ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-//     warp::path!("films" / String)
-//         .and(warp::put())
-//         .and(json_body())
-//         .and(with_db(dbclient))
-//         .and_then(handlers::update_film)
-// }
-
-/// DELETE /films/title
-// pub fn films_delete(
-//     dbclient: Client,
-// ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-//     warp::path!("films" / String)
-//         .and(warp::delete())
-//         .and(with_db(dbclient))
-//         .and_then(handlers::delete_film)
-// }
-
-fn with_db(
-    dbclient: Client,
-) -> impl Filter<Extract = (Client,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || dbclient.clone())
+/// POST /films/batch?upsert=true with a JSON array or NDJSON body
+pub fn films_import(
+    store: Store,
+    limit: u64,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("films" / "batch")
+        .and(warp::post())
+        .and(warp::query::<CreateOptions>())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(limited_body(limit))
+        .and(with_store(store))
+        .and_then(handlers::import_films)
+}
+
+/// PUT /films/{year}/{title} with JSON body
+pub fn films_update(
+    store: Store,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("films" / i32 / String)
+        .and(warp::put())
+        .and(json_body())
+        .and(with_store(store))
+        .and_then(handlers::update_film)
+}
+
+/// PATCH /films/{year}/{title} with a JSON Merge Patch body
+pub fn films_patch(
+    store: Store,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("films" / i32 / String)
+        .and(warp::patch())
+        .and(merge_patch_body())
+        .and(with_store(store))
+        .and_then(handlers::patch_film)
+}
+
+/// DELETE /films/{year}/{title}
+pub fn films_delete(
+    store: Store,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("films" / i32 / String)
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("prefer"))
+        .and(with_store(store))
+        .and_then(handlers::delete_film)
+}
+
+fn with_store(
+    store: Store,
+) -> impl Filter<Extract = (Store,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || store.clone())
 }
 
 fn json_body() -> impl Filter<Extract = (Film,), Error = warp::Rejection> + Clone {
@@ -77,3 +155,46 @@ fn json_body() -> impl Filter<Extract = (Film,), Error = warp::Rejection> + Clon
     // (and to reject huge payloads)...
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
 }
+
+fn merge_patch_body() -> impl Filter<Extract = (Bytes,), Error = warp::Rejection> + Clone {
+    // Merge patches are sent as `application/merge-patch+json`, which
+    // `warp::body::json` refuses, so they are parsed by the handler.
+    warp::body::content_length_limit(1024 * 16).and(warp::body::bytes())
+}
+
+// Unlike `content_length_limit`, this also allows chunked bodies, such as an
+// NDJSON stream, and stops reading once they pass `limit`.
+fn limited_body(limit: u64) -> impl Filter<Extract = (Bytes,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<u64>("content-length")
+        .and(warp::body::stream())
+        .and_then(move |length: Option<u64>, body| async move {
+            if length.is_some_and(|length| length > limit) {
+                return Err(ApiError::TooLarge(limit).into());
+            }
+            read_limited(body, limit)
+                .await
+                .map_err(warp::reject::custom)
+        })
+}
+
+async fn read_limited<S, B>(body: S, limit: u64) -> Result<Bytes, ApiError>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Unpin,
+    B: Buf,
+{
+    body.map_err(|e| ApiError::bad_request(format!("failed to read the body: {e}")))
+        .try_fold(Vec::new(), |mut bytes, mut chunk| async move {
+            if (bytes.len() + chunk.remaining()) as u64 > limit {
+                return Err(ApiError::TooLarge(limit));
+            }
+            while chunk.has_remaining() {
+                let part = chunk.chunk();
+                bytes.extend_from_slice(part);
+                let len = part.len();
+                chunk.advance(len);
+            }
+            Ok(bytes)
+        })
+        .await
+        .map(Bytes::from)
+}
@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiError;
+use crate::models::{Fields, Film, FilmError, FilmList, FILM_FIELDS};
+
+/// How a collection of films is written out, chosen with `?format=` or the
+/// `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Json,
+    /// One film per line.
+    Ndjson,
+    /// A header row, then one row per film with the columns in `CSV_COLUMNS`,
+    /// or only those asked for.
+    Csv,
+}
+
+/// The columns of a CSV listing, in the order they're written.
+pub const CSV_COLUMNS: [&str; 9] = FILM_FIELDS;
+
+/// Separates the items of `genres` and `cast` in a CSV cell.
+pub const CSV_LIST_SEPARATOR: char = '|';
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Ndjson => "application/x-ndjson",
+            Format::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    // The format a media range from `Accept` asks for, if it's one we have.
+    fn for_media_range(range: &str, default: Format) -> Option<Format> {
+        match range {
+            "*/*" => Some(default),
+            "application/*" if default == Format::Csv => Some(Format::Json),
+            "application/*" => Some(default),
+            "application/json" => Some(Format::Json),
+            "application/x-ndjson" | "application/ndjson" | "application/jsonl" => {
+                Some(Format::Ndjson)
+            }
+            "text/*" | "text/csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    /// The format to respond in: `requested` by `?format=` if it's given,
+    /// otherwise the supported type `accept` prefers most, and `default`
+    /// without an `Accept` header. Fails with 406 if nothing in `accept` is
+    /// supported.
+    pub fn negotiate(
+        requested: Option<Format>,
+        accept: Option<&str>,
+        default: Format,
+    ) -> Result<Format, ApiError> {
+        if let Some(format) = requested {
+            return Ok(format);
+        }
+        let Some(accept) = accept.filter(|accept| !accept.trim().is_empty()) else {
+            return Ok(default);
+        };
+
+        // The first of the most preferred ranges wins a tie.
+        let mut best: Option<(f32, Format)> = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_range = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q=")?.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let Some(format) = Format::for_media_range(&media_range, default) else {
+                continue;
+            };
+            if quality > 0.0 && best.is_none_or(|(q, _)| quality > q) {
+                best = Some((quality, format));
+            }
+        }
+        best.map(|(_, format)| format)
+            .ok_or_else(|| ApiError::NotAcceptable(accept.into()))
+    }
+
+    /// A page of films, each with only `fields` if they're given. JSON keeps
+    /// the `{"films": [...], "next": "..."}` envelope, while NDJSON and CSV
+    /// have only the films, leaving the next page to the `Link` header.
+    pub fn page(self, list: &FilmList, fields: Option<&Fields>) -> Result<Vec<u8>, FilmError> {
+        let head = match (self, fields) {
+            (Format::Json, None) => return json(list),
+            (Format::Json, Some(fields)) => {
+                let films = list
+                    .films
+                    .iter()
+                    .map(|film| fields.project(film))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut page = serde_json::Map::new();
+                page.insert("films".into(), films.into());
+                if let Some(next) = &list.next {
+                    page.insert("next".into(), next.as_str().into());
+                }
+                return json(&page);
+            }
+            (Format::Ndjson, _) => Vec::new(),
+            (Format::Csv, _) => csv_header(fields),
+        };
+        list.films.iter().try_fold(head, |mut body, film| {
+            body.append(&mut self.row(film, fields)?);
+            Ok(body)
+        })
+    }
+
+    /// One film as a line of NDJSON or a CSV row, or as JSON, with only
+    /// `fields` if they're given.
+    pub fn row(self, film: &Film, fields: Option<&Fields>) -> Result<Vec<u8>, FilmError> {
+        match (self, fields) {
+            (Format::Json, None) => json(film),
+            (Format::Json, Some(fields)) => json(&fields.project(film)?),
+            (Format::Ndjson, _) => {
+                let mut line = Format::Json.row(film, fields)?;
+                line.push(b'\n');
+                Ok(line)
+            }
+            (Format::Csv, _) => {
+                let row = CSV_COLUMNS
+                    .into_iter()
+                    .zip(csv_fields(film))
+                    .filter(|(column, _)| fields.is_none_or(|fields| fields.contains(column)))
+                    .map(|(_, value)| value);
+                csv_record(&row.collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
+fn json<T: Serialize>(value: &T) -> Result<Vec<u8>, FilmError> {
+    serde_json::to_vec(value).map_err(|e| FilmError::Unknown(e.to_string()))
+}
+
+/// The CSV header row, for `fields` if only they're wanted.
+pub fn csv_header(fields: Option<&Fields>) -> Vec<u8> {
+    // Nothing in the column names needs quoting, so this can't fail.
+    match fields {
+        Some(fields) => csv_record(fields.names()),
+        None => csv_record(&CSV_COLUMNS),
+    }
+    .unwrap_or_default()
+}
+
+fn csv_record<T: AsRef<[u8]>>(fields: &[T]) -> Result<Vec<u8>, FilmError> {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::CRLF)
+        .from_writer(Vec::new());
+    writer
+        .write_record(fields)
+        .map_err(|e| FilmError::Unknown(e.to_string()))?;
+    writer
+        .into_inner()
+        .map_err(|e| FilmError::Unknown(e.to_string()))
+}
+
+// A film's fields in `CSV_COLUMNS` order. Missing fields are empty.
+fn csv_fields(film: &Film) -> [String; 9] {
+    let text = |value: &Option<String>| value.clone().unwrap_or_default();
+    let number = |value: &Option<i32>| value.map(|n| n.to_string()).unwrap_or_default();
+    [
+        film.year.to_string(),
+        film.title.clone(),
+        csv_list(&film.genres),
+        csv_list(&film.cast),
+        text(&film.href),
+        text(&film.thumbnail),
+        number(&film.thumbnail_width),
+        number(&film.thumbnail_height),
+        text(&film.extract),
+    ]
+}
+
+// Joins list items with `CSV_LIST_SEPARATOR`, escaping any separator or
+// backslash in an item with a backslash so the cell splits unambiguously.
+fn csv_list(items: &[String]) -> String {
+    items
+        .iter()
+        .map(|item| {
+            item.replace('\\', "\\\\")
+                .replace(CSV_LIST_SEPARATOR, &format!("\\{CSV_LIST_SEPARATOR}"))
+        })
+        .collect::<Vec<_>>()
+        .join(&CSV_LIST_SEPARATOR.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Format;
+    use crate::{error::ApiError, models::Film};
+
+    #[test]
+    fn test_negotiate() {
+        let negotiate = |accept| Format::negotiate(None, accept, Format::Json);
+
+        assert_eq!(negotiate(None).unwrap(), Format::Json);
+        assert_eq!(negotiate(Some("*/*")).unwrap(), Format::Json);
+        assert_eq!(negotiate(Some("text/csv")).unwrap(), Format::Csv);
+        assert_eq!(
+            negotiate(Some("application/json;q=0.5, application/x-ndjson")).unwrap(),
+            Format::Ndjson
+        );
+        assert_eq!(
+            negotiate(Some("text/html, text/csv;q=0.9, */*;q=0.8")).unwrap(),
+            Format::Csv
+        );
+        assert!(matches!(
+            negotiate(Some("text/html, text/csv;q=0")),
+            Err(ApiError::NotAcceptable(_))
+        ));
+        assert_eq!(
+            Format::negotiate(Some(Format::Csv), Some("application/json"), Format::Json).unwrap(),
+            Format::Csv
+        );
+        assert_eq!(
+            Format::negotiate(None, Some("*/*"), Format::Ndjson).unwrap(),
+            Format::Ndjson
+        );
+    }
+
+    #[test]
+    fn test_csv_row() {
+        let mut film = Film::new(2019, "Knives Out".into());
+        film.genres = vec!["Mystery".into(), "Comedy".into()];
+        film.cast = vec!["Daniel Craig".into(), "Pipe | Dreams".into()];
+        film.thumbnail_width = Some(250);
+        film.extract = Some("A \"whodunit\", with a twist".into());
+
+        let row = String::from_utf8(Format::Csv.row(&film, None).unwrap()).unwrap();
+        assert_eq!(
+            row,
+            "2019,Knives Out,Mystery|Comedy,Daniel Craig|Pipe \\| Dreams,,,250,,\"A \"\"whodunit\"\", with a twist\"\r\n"
+        );
+    }
+}
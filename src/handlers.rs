@@ -1,93 +1,428 @@
-use crate::models::{Film, FixedResponse, ListOptions};
-use aws_sdk_dynamodb::{
-    types::{AttributeValue, PutRequest},
-    Client,
+use crate::error::ApiError;
+use crate::format::Format;
+use crate::models::{
+    CreateOptions, ExportOptions, Fields, Film, FilmError, FilmList, FilmPatch, FixedResponse,
+    GetOptions, ImportItem, ImportReport, ImportStatus, ListOptions, MAX_YEAR, MIN_YEAR,
 };
+use crate::store::{Cursor, FilmQuery, Imported, Page, Store};
+use crate::validation::{FieldError, Validate};
+use futures::{stream, StreamExt};
+use percent_encoding::percent_decode_str;
 use std::convert::Infallible;
-use tokio_stream::StreamExt;
-use warp::http::StatusCode;
+use warp::{
+    http::{header, StatusCode},
+    hyper::body::Bytes,
+    reply::Response,
+    Rejection, Reply,
+};
 
 pub async fn welcome(addr: Option<String>) -> Result<impl warp::Reply, Infallible> {
     Ok(warp::reply::json(&FixedResponse {
         status: StatusCode::OK.to_string(),
-        remote_address: String::from(addr.unwrap_or("unknown".into())),
+        remote_address: addr.unwrap_or("unknown".into()),
     }))
 }
 
+// Page size when the client doesn't ask for one, and the most it can ask for.
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+// The most films a page can skip. Skipped films are still read, so deep
+// pages should follow the cursor instead.
+const MAX_OFFSET: usize = MAX_LIMIT;
+
 pub async fn list_films(
     opts: ListOptions,
-    dbclient: Client,
-) -> Result<impl warp::Reply, Infallible> {
+    accept: Option<String>,
+    store: Store,
+) -> Result<Response, Rejection> {
     log::debug!("List films {:?}", opts);
-    match opts.year {
-        Some(y) => {
-            log::debug!("Year is {}", y);
-            let results = dbclient
-                .query()
-                .table_name("films")
-                .key_condition_expression("#yr = :yyyy")
-                .expression_attribute_names("#yr", "year")
-                .expression_attribute_values(":yyyy", AttributeValue::N(y.to_string()))
-                .send()
-                .await;
-            if let Some(items) = results.unwrap().items {
-                let films: Vec<Film> = items.iter().map(|v| v.into()).collect();
-                Ok(warp::reply::json(&films))
-            } else {
-                Ok(warp::reply::json(&FixedResponse {
-                    status: StatusCode::NOT_FOUND.to_string(),
-                    remote_address: "unknown".into(),
-                }))
-            }
+    let query = query(&opts)?;
+    list("/films", &opts, accept, query, store).await
+}
+
+pub async fn list_actor_films(
+    actor: String,
+    opts: ListOptions,
+    accept: Option<String>,
+    store: Store,
+) -> Result<Response, Rejection> {
+    log::debug!("List films for actor {} {:?}", actor, opts);
+    let path = format!("/actors/{actor}/films");
+    let query = FilmQuery {
+        actor: Some(decode(&actor)?),
+        ..query(&opts)?
+    };
+    list(&path, &opts, accept, query, store).await
+}
+
+fn query(opts: &ListOptions) -> Result<FilmQuery, ApiError> {
+    let years = match (opts.year, opts.from_year, opts.to_year) {
+        (Some(year), None, None) => Some(year..=year),
+        (None, None, None) => None,
+        // An open end of a range stops at the earliest or latest film.
+        (None, from, to) => Some(from.unwrap_or(MIN_YEAR)..=to.unwrap_or(MAX_YEAR)),
+        (Some(_), _, _) => {
+            return Err(ApiError::bad_request(
+                "year can't be combined with from_year or to_year",
+            ))
+        }
+    };
+    if years.as_ref().is_some_and(|years| years.is_empty()) {
+        return Err(ApiError::bad_request("from_year is after to_year"));
+    }
+    // A range runs one query per year, so it can't reach past the years a
+    // film can have.
+    let allowed = MIN_YEAR..=MAX_YEAR;
+    if years
+        .as_ref()
+        .is_some_and(|years| !allowed.contains(years.start()) || !allowed.contains(years.end()))
+    {
+        return Err(ApiError::bad_request(format!(
+            "years must be between {MIN_YEAR} and {MAX_YEAR}"
+        )));
+    }
+    Ok(FilmQuery {
+        years,
+        genre: opts.genre.clone(),
+        actor: opts.actor.clone(),
+        title: opts.title.clone(),
+        title_match: opts.title_match.unwrap_or_default(),
+        fields: fields(opts.fields.as_deref())?,
+    })
+}
+
+// `?fields=title,year`, rejecting names that aren't film fields.
+fn fields(fields: Option<&str>) -> Result<Option<Fields>, ApiError> {
+    fields
+        .map(|fields| fields.parse().map_err(ApiError::BadRequest))
+        .transpose()
+}
+
+async fn list(
+    path: &str,
+    opts: &ListOptions,
+    accept: Option<String>,
+    query: FilmQuery,
+    store: Store,
+) -> Result<Response, Rejection> {
+    let format = Format::negotiate(opts.format, accept.as_deref(), Format::Json)?;
+    let page = page(opts)?;
+    let results = store.list(&query, &page).await?;
+
+    let next = results.next.map(|cursor| cursor.encode());
+    let link = next.as_ref().map(|next| next_link(path, opts, next));
+    let body = format.page(
+        &FilmList {
+            films: results.films,
+            next,
+        },
+        query.fields.as_ref(),
+    )?;
+    let mut response = negotiated(format, body.into());
+    if let Some(link) = link {
+        if let Ok(link) = header::HeaderValue::from_str(&link) {
+            response.headers_mut().insert(header::LINK, link);
+        }
+    }
+    Ok(response)
+}
+
+// A response in `format`, which varies with the request's `Accept` header.
+fn negotiated(format: Format, body: warp::hyper::Body) -> Response {
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(format.content_type()),
+    );
+    headers.insert(header::VARY, header::HeaderValue::from_static("accept"));
+    response
+}
+
+fn page(opts: &ListOptions) -> Result<Page, ApiError> {
+    let limit = opts.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::bad_request(format!(
+            "limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+    let offset = opts.offset.unwrap_or_default();
+    if offset > MAX_OFFSET {
+        return Err(ApiError::bad_request(format!(
+            "offset must be at most {MAX_OFFSET}"
+        )));
+    }
+    let cursor = match &opts.cursor {
+        Some(cursor) => {
+            let cursor = Cursor::decode(cursor).filter(Cursor::is_well_formed);
+            Some(cursor.ok_or_else(|| ApiError::bad_request("invalid cursor"))?)
+        }
+        None => None,
+    };
+    Ok(Page {
+        offset,
+        limit,
+        cursor,
+    })
+}
+
+// `Link: </films?year=2020&cursor=...>; rel="next"`, keeping every other
+// query parameter. The offset was already applied to this page.
+fn next_link(path: &str, opts: &ListOptions, next: &str) -> String {
+    let opts = ListOptions {
+        offset: None,
+        cursor: Some(next.to_string()),
+        ..opts.clone()
+    };
+    let query = serde_urlencoded::to_string(&opts).unwrap_or_default();
+    format!("<{path}?{query}>; rel=\"next\"")
+}
+
+// Streams every film, as NDJSON unless another format is asked for, while
+// the store reads them. The status is sent before the first film is read,
+// so an error part way through ends the response early instead.
+pub async fn export_films(
+    opts: ExportOptions,
+    accept: Option<String>,
+    store: Store,
+) -> Result<Response, Rejection> {
+    let format = Format::negotiate(opts.format, accept.as_deref(), Format::Ndjson)?;
+    log::info!("export_films as {format:?}");
+    let rows = store.export().enumerate().map(move |(i, film)| {
+        let film = film.inspect_err(|e| log::warn!("Export failed: {e}"))?;
+        let mut row = format.row(&film, None)?;
+        if format == Format::Json && i > 0 {
+            row.insert(0, b',');
         }
-        //No year found return everything
-        None => {
-            let items: Result<Vec<_>, _> = dbclient
-                .scan()
-                .table_name("films")
-                .into_paginator()
-                .items()
-                .send()
-                .collect()
-                .await;
-
-            if items.is_ok() {
-                let mut v: Vec<Film> = Vec::new();
-                for i in items {
-                    for j in i {
-                        let f: Film = (&j).into();
-                        v.push(f);
-                    }
+        Ok::<_, FilmError>(row)
+    });
+    // JSON is a single array around the films, and CSV starts with a header.
+    let (head, tail) = match format {
+        Format::Json => (b"[".to_vec(), b"]\n".to_vec()),
+        Format::Ndjson => (Vec::new(), Vec::new()),
+        Format::Csv => (crate::format::csv_header(None), Vec::new()),
+    };
+    let body = stream::once(async { Ok(head) })
+        .chain(rows)
+        .chain(stream::once(async { Ok(tail) }));
+    Ok(negotiated(format, warp::hyper::Body::wrap_stream(body)))
+}
+
+pub async fn get_film(
+    year: i32,
+    title: String,
+    opts: GetOptions,
+    store: Store,
+) -> Result<Response, Rejection> {
+    let title = decode(&title)?;
+    log::debug!("get_film: year={} title={} {:?}", year, title, opts);
+    let reply = match fields(opts.fields.as_deref())? {
+        Some(fields) => match store.get_fields(year, &title, &fields).await? {
+            Some(film) => Some(warp::reply::json(&fields.project(&film)?)),
+            None => None,
+        },
+        None => store
+            .get(year, &title)
+            .await?
+            .map(|film| warp::reply::json(&film)),
+    };
+    match reply {
+        Some(reply) => Ok(reply.into_response()),
+        None => Err(ApiError::NotFound(year, title).into()),
+    }
+}
+
+pub async fn create_film(
+    opts: CreateOptions,
+    create: Film,
+    store: Store,
+) -> Result<Response, Rejection> {
+    log::debug!("create_film: {:?} {:?}", opts, create);
+    create.validate().map_err(ApiError::Invalid)?;
+    if opts.upsert {
+        store.put(&create).await?;
+    } else {
+        store.create(&create).await?;
+    }
+    Ok(StatusCode::CREATED.into_response())
+}
+
+pub async fn import_films(
+    opts: CreateOptions,
+    content_type: Option<String>,
+    body: Bytes,
+    store: Store,
+) -> Result<Response, Rejection> {
+    let items = parse_batch(content_type.as_deref(), &body)?;
+    log::debug!("import_films: {:?} {} items", opts, items.len());
+
+    // Invalid items are reported, and the rest are imported together.
+    let mut films = Vec::with_capacity(items.len());
+    let mut items: Vec<ImportItem> = items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let (film, errors) = match item {
+                Ok(film) => {
+                    let errors = film.validate().err().unwrap_or_default();
+                    (Some(film), errors)
                 }
-                return Ok(warp::reply::json(&v));
+                Err(error) => (None, vec![error]),
+            };
+            let item = ImportItem {
+                index,
+                status: match errors.is_empty() {
+                    true => ImportStatus::Created,
+                    false => ImportStatus::Invalid,
+                },
+                year: film.as_ref().map(|film| film.year),
+                title: film.as_ref().map(|film| film.title.clone()),
+                errors,
+            };
+            if let Some(film) = film.filter(|_| item.status == ImportStatus::Created) {
+                films.push(film);
             }
+            item
+        })
+        .collect();
 
-            Ok(warp::reply::json(&FixedResponse {
-                status: StatusCode::NOT_FOUND.to_string(),
-                remote_address: "unknown".into(),
-            }))
+    let mut outcomes = store.import(&films, opts.upsert).await?.into_iter();
+    let mut report = ImportReport::default();
+    for item in items.iter_mut() {
+        if item.status == ImportStatus::Created {
+            item.status = match outcomes.next() {
+                Some(Imported::Created) => ImportStatus::Created,
+                Some(Imported::Conflicted) => ImportStatus::Conflicted,
+                Some(Imported::Failed) | None => ImportStatus::Failed,
+            };
         }
     }
+    items.into_iter().for_each(|item| report.push(item));
+    Ok(warp::reply::json(&report).into_response())
 }
 
-pub async fn create_film(create: Film, dbclient: Client) -> Result<impl warp::Reply, Infallible> {
-    log::debug!("create_film: {:?}", create);
-    let putreq: PutRequest = (&create).into();
-    match dbclient
-        .put_item()
-        .table_name("films")
-        .set_item(putreq.item().cloned())
-        .send()
-        .await
-    {
-        Ok(_) => return Ok(StatusCode::CREATED),
-        Err(e) => {
-            log::warn!("Error! {}", e);
-            return Ok(StatusCode::INTERNAL_SERVER_ERROR);
+// A JSON array of films, or one film per line for `application/x-ndjson`.
+// An item that isn't a film is reported as invalid, but a body that isn't
+// an array or NDJSON at all is rejected.
+fn parse_batch(
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<Vec<Result<Film, FieldError>>, ApiError> {
+    let not_a_film = |e: serde_json::Error| FieldError {
+        field: String::new(),
+        message: format!("not a film: {e}"),
+    };
+    let ndjson = content_type
+        .and_then(|ct| ct.split(';').next())
+        .is_some_and(|ct| {
+            matches!(
+                ct.trim(),
+                "application/x-ndjson" | "application/ndjson" | "application/jsonl"
+            )
+        });
+    if ndjson {
+        let body = std::str::from_utf8(body)
+            .map_err(|_| ApiError::bad_request("the body is not valid UTF-8"))?;
+        Ok(body
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(not_a_film))
+            .collect())
+    } else {
+        let values: Vec<serde_json::Value> = serde_json::from_slice(body)
+            .map_err(|e| ApiError::bad_request(format!("expected an array of films: {e}")))?;
+        Ok(values
+            .into_iter()
+            .map(|value| serde_json::from_value(value).map_err(not_a_film))
+            .collect())
+    }
+}
+
+pub async fn update_film(
+    year: i32,
+    title: String,
+    update: Film,
+    store: Store,
+) -> Result<Response, Rejection> {
+    let title = decode(&title)?;
+    if title != update.title || year != update.year {
+        // The key comes from the path and can't be changed by the body.
+        return Err(ApiError::bad_request("the film's year and title can't be changed").into());
+    }
+    log::debug!("update_film: {:?}", update);
+    update.validate().map_err(ApiError::Invalid)?;
+    apply_patch(year, title, &(&update).into(), store).await
+}
+
+pub async fn patch_film(
+    year: i32,
+    title: String,
+    body: Bytes,
+    store: Store,
+) -> Result<Response, Rejection> {
+    let title = decode(&title)?;
+    let patch: FilmPatch = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::bad_request(format!("invalid merge patch: {e}")))?;
+    log::debug!("patch_film: year={} title={} {:?}", year, title, patch);
+    patch.validate().map_err(ApiError::Invalid)?;
+    apply_patch(year, title, &patch, store).await
+}
+
+async fn apply_patch(
+    year: i32,
+    title: String,
+    patch: &FilmPatch,
+    store: Store,
+) -> Result<Response, Rejection> {
+    match store.update(year, &title, patch).await? {
+        Some(film) => Ok(warp::reply::json(&film).into_response()),
+        None => Err(ApiError::NotFound(year, title).into()),
+    }
+}
+
+pub async fn delete_film(
+    year: i32,
+    title: String,
+    prefer: Option<String>,
+    store: Store,
+) -> Result<Response, Rejection> {
+    let title = decode(&title)?;
+    log::info!("delete_film: year={} title={}", year, title);
+
+    match store.delete(year, &title).await? {
+        Some(film) if wants_representation(prefer.as_deref()) => {
+            Ok(warp::reply::json(&film).into_response())
         }
+        // respond with a `204 No Content`, which means successful,
+        // yet no body expected...
+        Some(_) => Ok(StatusCode::NO_CONTENT.into_response()),
+        None => Err(ApiError::NotFound(year, title).into()),
     }
 }
 
+// `Prefer: return=representation` (RFC 7240) asks for the deleted film back.
+fn wants_representation(prefer: Option<&str>) -> bool {
+    prefer
+        .map(|prefer| {
+            prefer
+                .split(',')
+                .any(|p| p.trim().eq_ignore_ascii_case("return=representation"))
+        })
+        .unwrap_or(false)
+}
+
+// Path segments arrive percent-encoded, e.g. `Knives%20Out`. This uses
+// `percent-encoding` rather than `urldecode`, which turns each escaped byte
+// into its own char, mangling UTF-8 titles like `Am%C3%A9lie`, and panics on
+// an escape that's cut short or isn't hex.
+fn decode(segment: &str) -> Result<String, ApiError> {
+    percent_decode_str(segment)
+        .decode_utf8()
+        .map(|segment| segment.into_owned())
+        .map_err(|_| ApiError::bad_request("path segment is not valid UTF-8"))
+}
+
 // pub async fn query_item(client: &Client, item: Film) -> bool {
 //     let value = &item.value;
 //     let key = &item.key;
@@ -120,37 +455,3 @@ pub async fn create_film(create: Film, dbclient: Client) -> Result<impl warp::Re
 //         }
 //     }
 // }
-// pub async fn update_film(
-//     title: String,
-//     update: Film,
-//     db: Db,
-// ) -> Result<impl warp::Reply, Infallible> {
-//     // If the for loop didn't return OK, then the ID doesn't exist...
-//     Ok(StatusCode::NOT_FOUND)
-// }
-
-// pub async fn delete_film(title: String, db: Db) -> Result<impl warp::Reply, Infallible> {
-//     let title = decode(title);
-//     log::info!("delete_film: id={}", title);
-
-//     let mut vec = db.lock().await;
-
-//     let len = vec.len();
-//     vec.retain(|film| {
-//         // Retain all films that aren't this id...
-//         // In other words, remove all that *are* this id...
-//         film.title != title
-//     });
-
-//     // If the vec is smaller, we found and deleted a film!
-//     let deleted = vec.len() != len;
-
-//     if deleted {
-//         // respond with a `204 No Content`, which means successful,
-//         // yet no body expected...
-//         Ok(StatusCode::NO_CONTENT)
-//     } else {
-//         log::debug!("    -> film id not found!");
-//         Ok(StatusCode::NOT_FOUND)
-//     }
-// }
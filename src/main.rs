@@ -2,13 +2,25 @@ use std::env;
 extern crate log;
 extern crate tokio;
 use aws_sdk_dynamodb::{config::Region, Client};
-use ddb::initialize;
+use clap::Parser;
+use config::{Backend, Cli, Config, StartupMode};
+use dataset::Dataset;
+use ddb::{initialize, wait::Wait};
+use std::{process, sync::Arc, time::Duration};
+use store::{DynamoFilmStore, MemoryFilmStore, Store};
+use tokio_util::sync::CancellationToken;
 use warp::Filter;
 
+mod config;
+mod dataset;
 mod ddb;
+mod error;
 mod filters;
+mod format;
 mod handlers;
 mod models;
+mod store;
+mod validation;
 
 #[tokio::main]
 async fn main() {
@@ -18,27 +30,121 @@ async fn main() {
         env::set_var("RUST_LOG", "films=debug");
     }
     pretty_env_logger::init();
-    let config = aws_config::from_env()
-        .region(Region::new("us-east-1"))
-        .load()
-        .await;
-    let dynamodb_local_config = aws_sdk_dynamodb::config::Builder::from(&config)
-        .endpoint_url(
-            // 8000 is the default dynamodb port
-            "http://localhost:8000",
-        )
-        .build();
 
-    let db_client = Client::from_conf(dynamodb_local_config);
-    let _ = db_client.delete_table().table_name("films").send().await;
+    let config = match Config::load(Cli::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{e}");
+            process::exit(2);
+        }
+    };
+    log::debug!("Config: {:?}", config);
 
-    let _ = initialize(&db_client, "films").await;
-    let api = filters::films(db_client);
+    // Ctrl-C stops waiting for tables at startup, and then stops the server.
+    let shutdown = CancellationToken::new();
+    let interrupted = shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            log::info!("Interrupted, shutting down");
+            interrupted.cancel();
+        }
+    });
+
+    // Use `--store memory` (or `FILMS_STORE=memory`) to run without DynamoDB Local.
+    let store: Store = match config.store {
+        Backend::Memory => {
+            log::info!("Using in-memory film store");
+            Arc::new(MemoryFilmStore::new())
+        }
+        Backend::Dynamo => {
+            let store = tokio::select! {
+                store = dynamo_store(&config, &shutdown) => store,
+                _ = shutdown.cancelled() => process::exit(130),
+            };
+            match store {
+                Ok(store) => Arc::new(store),
+                Err(e) => {
+                    log::error!("Failed to set up DynamoDB tables: {e}");
+                    process::exit(1);
+                }
+            }
+        }
+    };
+    let api = filters::films(store, config.batch_limit);
 
     // View access logs by setting `RUST_LOG=films`.
     let routes = api.with(warp::log("films-api"));
     // Start up the server...
-    warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
+    let (_, server) =
+        warp::serve(routes).bind_with_graceful_shutdown(config.listen, shutdown.cancelled_owned());
+    server.await;
+}
+
+async fn dynamo_store(
+    config: &Config,
+    shutdown: &CancellationToken,
+) -> Result<DynamoFilmStore, ddb::error::Error> {
+    let sdk_config = aws_config::from_env()
+        .region(Region::new(config.region.clone()))
+        .load()
+        .await;
+    let mut builder = aws_sdk_dynamodb::config::Builder::from(&sdk_config);
+    if let Some(endpoint) = &config.endpoint {
+        builder = builder.endpoint_url(endpoint);
+    }
+
+    let db_client = Client::from_conf(builder.build());
+    let table = config.table.as_str();
+    let seed = seed_dataset(config, &sdk_config);
+    let seed = seed.as_ref();
+    let wait = Wait::new(
+        Duration::from_secs(config.table_wait_secs),
+        shutdown.clone(),
+    );
+    match config.startup {
+        StartupMode::Ensure => {
+            initialize(
+                &db_client,
+                table,
+                config.capacity,
+                seed,
+                config.load_concurrency,
+                &wait,
+            )
+            .await?
+        }
+        StartupMode::Recreate => {
+            log::warn!("Recreating {table}, deleting every film in it");
+            ddb::drop_tables(&db_client, table, &wait).await?;
+            initialize(
+                &db_client,
+                table,
+                config.capacity,
+                seed,
+                config.load_concurrency,
+                &wait,
+            )
+            .await?
+        }
+        StartupMode::VerifyOnly => ddb::verify(&db_client, table).await?,
+    }
+    Ok(DynamoFilmStore::new(db_client, table).with_scan_segments(config.scan_segments))
+}
+
+fn seed_dataset(config: &Config, sdk_config: &aws_config::SdkConfig) -> Option<Dataset> {
+    if !config.seed {
+        return None;
+    }
+    let Some(source) = &config.dataset else {
+        log::info!("No dataset configured, new tables will be empty");
+        return None;
+    };
+    let mut builder = aws_sdk_s3::config::Builder::from(sdk_config);
+    if let Some(endpoint) = &config.s3_endpoint {
+        builder = builder.endpoint_url(endpoint).force_path_style(true);
+    }
+    let s3 = aws_sdk_s3::Client::from_conf(builder.build());
+    Some(Dataset::new(source.clone(), s3))
 }
 
 //Tests
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 
 use aws_sdk_dynamodb::types::{AttributeValue, PutRequest};
 use aws_smithy_client::SdkError;
@@ -6,6 +6,9 @@ use serde::{Deserialize, Serialize};
 //use serde_json::Value;
 use thiserror::Error;
 
+use crate::format::Format;
+use crate::validation::FieldError;
+
 #[derive(Error, Debug)]
 pub enum FilmError {
     // #[error("failed to parse serde_json::Value into Film {0}")]
@@ -14,7 +17,16 @@ pub enum FilmError {
     FromSerde(serde_dynamo::Error),
 
     #[error("aws_sdk_dynamodb error: {0}")]
-    Dynamo(aws_sdk_dynamodb::Error),
+    Dynamo(Box<aws_sdk_dynamodb::Error>),
+
+    #[error("DynamoDB table error: {0}")]
+    Table(#[from] crate::ddb::error::Error),
+
+    #[error("film already exists: {0} ({1})")]
+    AlreadyExists(String, i32),
+
+    #[error("invalid cursor: {0}")]
+    InvalidCursor(String),
 
     #[error("unknown DynamoDB films error: {0}")]
     Unknown(String),
@@ -22,7 +34,7 @@ pub enum FilmError {
 
 impl From<aws_sdk_dynamodb::Error> for FilmError {
     fn from(err: aws_sdk_dynamodb::Error) -> Self {
-        FilmError::Dynamo(err)
+        FilmError::Dynamo(Box::new(err))
     }
 }
 
@@ -41,7 +53,7 @@ where
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Film {
     pub year: i32,
     pub title: String,
@@ -88,99 +100,186 @@ impl Film {
     }
 }
 
-fn as_string(val: Option<&AttributeValue>, default: &String) -> String {
-    if let Some(v) = val {
-        if let Ok(s) = v.as_s() {
-            return s.to_owned();
-        }
+/// A film's attributes in the order they're written out, which are also the
+/// names `?fields=` accepts.
+pub const FILM_FIELDS: [&str; 9] = [
+    "year",
+    "title",
+    "genres",
+    "cast",
+    "href",
+    "thumbnail",
+    "thumbnail_width",
+    "thumbnail_height",
+    "extract",
+];
+
+/// Some of a film's attributes, parsed from `?fields=title,year,cast` and
+/// kept in `FILM_FIELDS` order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fields(Vec<&'static str>);
+
+impl Fields {
+    /// Just the key, `year` and `title`.
+    pub fn key() -> Self {
+        Fields(vec!["year", "title"])
+    }
+
+    pub fn names(&self) -> &[&'static str] {
+        &self.0
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains(&name)
+    }
+
+    /// These fields and `name`, which must be one of `FILM_FIELDS`.
+    pub fn with(&self, name: &'static str) -> Self {
+        let fields = FILM_FIELDS
+            .into_iter()
+            .filter(|field| *field == name || self.contains(field));
+        Fields(fields.collect())
+    }
+
+    /// `film` as a JSON object with only these fields.
+    pub fn project(&self, film: &Film) -> Result<serde_json::Value, FilmError> {
+        let mut film = match serde_json::to_value(film) {
+            Ok(serde_json::Value::Object(film)) => film,
+            Ok(_) => return Err(FilmError::Unknown("a film isn't a JSON object".into())),
+            Err(e) => return Err(FilmError::Unknown(e.to_string())),
+        };
+        film.retain(|name, _| self.contains(name));
+        Ok(film.into())
     }
-    default.to_owned()
 }
 
-fn as_i32(val: Option<&AttributeValue>, default: i32) -> i32 {
-    if let Some(v) = val {
-        if let Ok(n) = v.as_n() {
-            if let Ok(n) = n.parse::<i32>() {
-                return n;
+impl FromStr for Fields {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut wanted = Vec::new();
+        for name in s.split(',').map(str::trim) {
+            match FILM_FIELDS.iter().find(|field| **field == name) {
+                Some(field) => wanted.push(*field),
+                None => {
+                    return Err(format!(
+                        "unknown field {name:?} in fields, expected some of {}",
+                        FILM_FIELDS.join(",")
+                    ))
+                }
             }
         }
+        Ok(Fields(
+            FILM_FIELDS
+                .into_iter()
+                .filter(|field| wanted.contains(field))
+                .collect(),
+        ))
     }
-    default
 }
 
-fn as_string_vec(val: Option<&AttributeValue>) -> Vec<String> {
-    if let Some(val) = val {
-        if let Ok(val) = val.as_l() {
-            return val
-                .iter()
-                .map(|v| as_string(Some(v), &"".to_string()))
-                .collect();
+/// A JSON Merge Patch (RFC 7396) for the non-key fields of a film. An absent
+/// field is left alone, `null` removes it and any other value replaces it.
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FilmPatch {
+    #[serde(default, deserialize_with = "patch_field")]
+    pub genres: Option<Option<Vec<String>>>,
+    #[serde(alias = "actors", default, deserialize_with = "patch_field")]
+    pub cast: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "patch_field")]
+    pub href: Option<Option<String>>,
+    #[serde(default, deserialize_with = "patch_field")]
+    pub thumbnail: Option<Option<String>>,
+    #[serde(default, deserialize_with = "patch_field")]
+    pub thumbnail_width: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "patch_field")]
+    pub thumbnail_height: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "patch_field")]
+    pub extract: Option<Option<String>>,
+}
+
+// Distinguishes an explicit `null` (Some(None)) from a missing field (None).
+fn patch_field<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl FilmPatch {
+    pub fn is_empty(&self) -> bool {
+        *self == FilmPatch::default()
+    }
+
+    pub fn apply(&self, film: &mut Film) {
+        if let Some(genres) = &self.genres {
+            film.genres = genres.clone().unwrap_or_default();
+        }
+        if let Some(cast) = &self.cast {
+            film.cast = cast.clone().unwrap_or_default();
+        }
+        if let Some(href) = &self.href {
+            film.href = href.clone();
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            film.thumbnail = thumbnail.clone();
+        }
+        if let Some(width) = self.thumbnail_width {
+            film.thumbnail_width = width;
+        }
+        if let Some(height) = self.thumbnail_height {
+            film.thumbnail_height = height;
+        }
+        if let Some(extract) = &self.extract {
+            film.extract = extract.clone();
         }
     }
-    vec![]
 }
 
-impl From<&HashMap<String, AttributeValue>> for Film {
-    fn from(value: &HashMap<String, AttributeValue>) -> Self {
-        let mut film = Film::new(
-            as_i32(value.get("year"), 0),
-            as_string(value.get("title"), &"".to_string()),
-        );
+/// A patch that replaces every non-key field with the film's value, used
+/// for full updates.
+impl From<&Film> for FilmPatch {
+    fn from(film: &Film) -> Self {
+        FilmPatch {
+            genres: Some(Some(film.genres.clone())),
+            cast: Some(Some(film.cast.clone())),
+            href: Some(film.href.clone()),
+            thumbnail: Some(film.thumbnail.clone()),
+            thumbnail_width: Some(film.thumbnail_width),
+            thumbnail_height: Some(film.thumbnail_height),
+            extract: Some(film.extract.clone()),
+        }
+    }
+}
 
-        let mut genres: Vec<String> = as_string_vec(value.get("genres"));
-        let mut cast: Vec<String> = as_string_vec(value.get("cast"));
+/// Case-folded form used to match genres and titles case-insensitively.
+pub fn normalize(value: &str) -> String {
+    value.trim().to_lowercase()
+}
 
-        film.genres_mut().append(&mut genres);
-        film.cast_mut().append(&mut cast);
+impl TryFrom<&HashMap<String, AttributeValue>> for Film {
+    type Error = FilmError;
 
-        film
+    fn try_from(item: &HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        // `title_normalized` and any other extra attributes are ignored.
+        Ok(serde_dynamo::from_item(item.clone())?)
     }
 }
 
-impl From<&Film> for PutRequest {
-    fn from(film: &Film) -> Self {
-        PutRequest::builder()
-            .item("year", AttributeValue::N(film.year.to_string()))
-            .item("title", AttributeValue::S(film.title.clone()))
-            .item(
-                "cast",
-                AttributeValue::L(
-                    film.cast
-                        .iter()
-                        .map(|v| AttributeValue::S(v.clone()))
-                        .collect(),
-                ),
-            )
-            .item(
-                "genres",
-                AttributeValue::L(
-                    film.genres
-                        .iter()
-                        .map(|v| AttributeValue::S(v.clone()))
-                        .collect(),
-                ),
-            )
-            .item(
-                "href",
-                AttributeValue::S(film.href.clone().unwrap_or("default".into())),
-            )
-            .item(
-                "thumbnail",
-                AttributeValue::S(film.thumbnail.clone().unwrap_or("default".into())),
-            )
-            .item(
-                "thumbnail_height",
-                AttributeValue::N(film.thumbnail_height.unwrap_or(0).to_string()),
-            )
-            .item(
-                "thumbnail_width",
-                AttributeValue::N(film.thumbnail_width.unwrap_or(0).to_string()),
-            )
-            .item(
-                "extract",
-                AttributeValue::S(film.extract.clone().unwrap_or("".into())),
-            )
-            .build()
+impl TryFrom<&Film> for PutRequest {
+    type Error = FilmError;
+
+    fn try_from(film: &Film) -> Result<Self, Self::Error> {
+        let mut item: HashMap<String, AttributeValue> = serde_dynamo::to_item(film)?;
+        // Missing optionals are left out of the item instead of stored as NULL.
+        item.retain(|_, value| !value.is_null());
+        item.insert(
+            "title_normalized".into(),
+            AttributeValue::S(normalize(&film.title)),
+        );
+        Ok(PutRequest::builder().set_item(Some(item)).build())
     }
 }
 
@@ -188,7 +287,23 @@ impl From<&Film> for PutRequest {
 mod test {
     use aws_sdk_dynamodb::types::PutRequest;
 
-    use super::Film;
+    use super::{Fields, Film, FilmPatch};
+
+    #[test]
+    fn test_parse_fields() {
+        let fields: Fields = "cast, year,title,year".parse().unwrap();
+        assert_eq!(fields.names(), ["year", "title", "cast"]);
+        assert_eq!(
+            fields.with("genres").names(),
+            ["year", "title", "genres", "cast"]
+        );
+        assert!("title,plot".parse::<Fields>().is_err());
+        assert!("".parse::<Fields>().is_err());
+
+        let film = Film::new(2019, "Knives Out".into());
+        let projected = "title".parse::<Fields>().unwrap().project(&film).unwrap();
+        assert_eq!(projected, serde_json::json!({"title": "Knives Out"}));
+    }
 
     #[test]
     fn test_put_request_from_film_and_back() {
@@ -201,14 +316,44 @@ mod test {
         film.genres_mut()
             .append(&mut vec!["Mystery".into(), "Comedy".into()]);
 
-        let request: PutRequest = (&film).into();
+        let request = PutRequest::try_from(&film).unwrap();
 
         let item = request.item().unwrap();
-        assert_eq!(item.len(), 9);
+        assert_eq!(item.len(), 5);
+        assert!(!item.contains_key("href"));
 
-        let film_back: Film = item.into();
+        let film_back = Film::try_from(item).unwrap();
 
         assert_eq!(film_back, film);
+
+        film.href = Some("Knives_Out".into());
+        film.thumbnail = Some("http://example.com/knives.jpg".into());
+        *film.thumbnail_width() = Some(320);
+        *film.thumbnail_height() = Some(480);
+        film.extract = Some("A detective investigates".into());
+
+        let request = PutRequest::try_from(&film).unwrap();
+        let item = request.item().unwrap();
+        assert_eq!(item.len(), 10);
+        assert_eq!(Film::try_from(item).unwrap(), film);
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut film = Film::new(2022, "Knives Out".into());
+        film.genres_mut().push("Mystery".into());
+        film.extract = Some("A detective investigates".into());
+
+        let patch: FilmPatch = serde_json::from_str(
+            r#"{"genres": ["Comedy"], "extract": null, "href": "Knives_Out"}"#,
+        )
+        .unwrap();
+        patch.apply(&mut film);
+
+        assert_eq!(film.genres, vec!["Comedy".to_string()]);
+        assert_eq!(film.extract, None);
+        assert_eq!(film.href, Some("Knives_Out".into()));
+        assert!(serde_json::from_str::<FilmPatch>(r#"{"year": 2023}"#).is_err());
     }
 }
 
@@ -219,12 +364,110 @@ pub struct FixedResponse {
     pub remote_address: String,
 }
 
+// The range of years a film can be from.
+pub const MIN_YEAR: i32 = 1870;
+pub const MAX_YEAR: i32 = 2100;
+
+// The query parameters for create film.
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateOptions {
+    #[serde(default)]
+    pub upsert: bool,
+}
+
+// How `ListOptions.title` is matched. Exact and prefix matches are
+// case-sensitive, contains ignores case.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TitleMatch {
+    Exact,
+    Prefix,
+    #[default]
+    Contains,
+}
+
 // The query parameters for list films.
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ListOptions {
     pub offset: Option<usize>,
     pub limit: Option<usize>,
+    pub cursor: Option<String>,
     pub title: Option<String>,
+    #[serde(rename = "match")]
+    pub title_match: Option<TitleMatch>,
     pub genre: Option<String>,
-    pub year: Option<u16>,
+    pub actor: Option<String>,
+    pub year: Option<i32>,
+    pub from_year: Option<i32>,
+    pub to_year: Option<i32>,
+    /// Overrides the `Accept` header.
+    pub format: Option<Format>,
+    /// Comma-separated `Fields` to return instead of the whole film.
+    pub fields: Option<String>,
+}
+
+// The query parameters for get film.
+#[derive(Debug, Default, Deserialize)]
+pub struct GetOptions {
+    /// Comma-separated `Fields` to return instead of the whole film.
+    pub fields: Option<String>,
+}
+
+// The query parameters for exporting films.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ExportOptions {
+    /// Overrides the `Accept` header.
+    pub format: Option<Format>,
+}
+
+// The result of `POST /films/batch`: counts of each status, and what
+// happened to every item in the order they were sent.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ImportReport {
+    pub created: usize,
+    pub conflicted: usize,
+    pub invalid: usize,
+    pub failed: usize,
+    pub items: Vec<ImportItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportItem {
+    pub index: usize,
+    pub status: ImportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Created,
+    Conflicted,
+    Invalid,
+    Failed,
+}
+
+impl ImportReport {
+    pub fn push(&mut self, item: ImportItem) {
+        match item.status {
+            ImportStatus::Created => self.created += 1,
+            ImportStatus::Conflicted => self.conflicted += 1,
+            ImportStatus::Invalid => self.invalid += 1,
+            ImportStatus::Failed => self.failed += 1,
+        }
+        self.items.push(item);
+    }
+}
+
+// A page of films, with the cursor to pass as `?cursor=` for the next page.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FilmList {
+    pub films: Vec<Film>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
 }
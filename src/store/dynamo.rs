@@ -0,0 +1,1320 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    ops::RangeInclusive,
+};
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{
+    types::{AttributeValue, KeysAndAttributes, PutRequest, ReturnValue, WriteRequest},
+    Client,
+};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use serde_json::Value;
+
+use super::{repeated_keys, Cursor, FilmPage, FilmQuery, FilmStore, Imported, Page};
+use crate::ddb;
+use crate::models::{normalize, Fields, Film, FilmError, FilmPatch, TitleMatch};
+
+// BatchGetItem reads at most this many keys per request.
+const BATCH_GET_SIZE: usize = 100;
+
+// How many years of a year range are queried at once.
+const YEAR_QUERIES: usize = 8;
+
+// How many imported films have their index items synced at once.
+const INDEX_SYNCS: usize = 8;
+
+/// `FilmStore` backed by a DynamoDB table with `year` as the hash key and
+/// `title` as the range key, as created by `ddb::create_table`, and the
+/// index table from `ddb::create_index_table` for genre and cast lookups.
+#[derive(Clone, Debug)]
+pub struct DynamoFilmStore {
+    client: Client,
+    table: String,
+    index_table: String,
+    scan_segments: u32,
+}
+
+impl DynamoFilmStore {
+    pub fn new(client: Client, table: impl Into<String>) -> Self {
+        let table = table.into();
+        DynamoFilmStore {
+            client,
+            index_table: ddb::index_table_name(&table),
+            table,
+            scan_segments: ddb::scan::DEFAULT_SCAN_SEGMENTS,
+        }
+    }
+
+    /// Reads listings and exports that scan the whole table as `segments`
+    /// parallel segments.
+    pub fn with_scan_segments(mut self, segments: u32) -> Self {
+        self.scan_segments = segments;
+        self
+    }
+
+    async fn query_year(
+        &self,
+        year: i32,
+        query: &FilmQuery,
+        page: &Page,
+    ) -> Result<FilmPage, FilmError> {
+        let exprs = &Expressions::default()
+            .key("#yr = :yyyy")
+            .name("#yr", "year")
+            .value(":yyyy", AttributeValue::N(year.to_string()));
+        let exprs = &with_title(exprs.clone(), query, true).project(query.fields.as_ref());
+        collect_page(page, TABLE_KEY, |start, limit| async move {
+            let output = self
+                .client
+                .query()
+                .table_name(&self.table)
+                .set_key_condition_expression(exprs.key_condition())
+                .set_projection_expression(exprs.projection())
+                .set_filter_expression(exprs.filter_expression())
+                .set_expression_attribute_names(exprs.names())
+                .set_expression_attribute_values(exprs.values())
+                .set_exclusive_start_key(start)
+                .limit(exprs.limit(limit))
+                .send()
+                .await?;
+            Ok((
+                output.items().unwrap_or_default().to_vec(),
+                output.last_evaluated_key().cloned(),
+            ))
+        })
+        .await
+    }
+
+    // `year` is the hash key, so a range of years is one Query per year. The
+    // queries run concurrently and are merged in (year, title) order,
+    // stopping once the page is full.
+    async fn query_years(
+        &self,
+        years: RangeInclusive<i32>,
+        query: &FilmQuery,
+        page: &Page,
+    ) -> Result<FilmPage, FilmError> {
+        if page
+            .cursor
+            .as_ref()
+            .is_some_and(|cursor| !cursor.is_key(TABLE_KEY))
+        {
+            return Err(FilmError::InvalidCursor(format!(
+                "expected a cursor with {}",
+                TABLE_KEY.join(" and ")
+            )));
+        }
+        let wanted = page.offset.saturating_add(page.limit);
+        let cursor_year = page.cursor.as_ref().and_then(Cursor::year);
+        let first = cursor_year.map_or(*years.start(), |year| year.max(*years.start()));
+        let last = *years.end();
+
+        let mut pages = futures::stream::iter(first..=last)
+            .map(|year| {
+                let year_page = Page {
+                    offset: 0,
+                    limit: wanted,
+                    cursor: page.cursor.clone().filter(|_| cursor_year == Some(year)),
+                };
+                async move { (year, self.query_year(year, query, &year_page).await) }
+            })
+            .buffered(YEAR_QUERIES);
+
+        let mut films = Vec::new();
+        let mut more = false;
+        while let Some((year, result)) = pages.next().await {
+            let result = result?;
+            films.extend(result.films);
+            if films.len() >= wanted {
+                more = films.len() > wanted || result.next.is_some() || year < last;
+                break;
+            }
+        }
+
+        films.truncate(wanted);
+        let next = if more {
+            films.last().map(Cursor::after)
+        } else {
+            None
+        };
+        Ok(FilmPage {
+            films: films.into_iter().skip(page.offset).collect(),
+            next,
+        })
+    }
+
+    // Without a year or term to narrow it down, a listing scans the table in
+    // parallel segments. The films still wanted are shared out between the
+    // segments that have more, which are read concurrently, and the cursor
+    // records where each segment got to.
+    async fn scan(&self, query: &FilmQuery, page: &Page) -> Result<FilmPage, FilmError> {
+        let exprs =
+            &with_title(Expressions::default(), query, false).project(query.fields.as_ref());
+        let total = ddb::scan::scan_segments(self.scan_segments) as usize;
+        let mut segments = match &page.cursor {
+            Some(cursor) => Segment::from_cursor(cursor, total)?,
+            None => vec![Segment::Start; total],
+        };
+        let wanted = page.offset.saturating_add(page.limit);
+
+        let mut films = Vec::new();
+        loop {
+            let active: Vec<usize> = (0..segments.len())
+                .filter(|&i| segments[i] != Segment::Done)
+                .collect();
+            let room = wanted - films.len();
+            if room == 0 || active.is_empty() {
+                break;
+            }
+            let reads = active.iter().enumerate().filter_map(|(n, &i)| {
+                let share = room / active.len() + usize::from(n < room % active.len());
+                let cursor = match &segments[i] {
+                    Segment::After(cursor) => Some(cursor.clone()),
+                    _ => None,
+                };
+                let segment_page = Page {
+                    offset: 0,
+                    limit: share,
+                    cursor,
+                };
+                (share > 0).then_some(async move {
+                    let read = self.scan_segment(exprs, i as i32, total as i32, &segment_page);
+                    Ok::<_, FilmError>((i, read.await?))
+                })
+            });
+            for (i, read) in futures::future::try_join_all(reads).await? {
+                films.extend(read.films);
+                segments[i] = read.next.map_or(Segment::Done, Segment::After);
+            }
+        }
+
+        let next = segments
+            .iter()
+            .any(|segment| *segment != Segment::Done)
+            .then(|| Segment::to_cursor(&segments));
+        Ok(FilmPage {
+            films: films.into_iter().skip(page.offset).collect(),
+            next,
+        })
+    }
+
+    async fn scan_segment(
+        &self,
+        exprs: &Expressions,
+        segment: i32,
+        total: i32,
+        page: &Page,
+    ) -> Result<FilmPage, FilmError> {
+        collect_page(page, TABLE_KEY, |start, limit| async move {
+            let output = self
+                .client
+                .scan()
+                .table_name(&self.table)
+                .segment(segment)
+                .total_segments(total)
+                .set_projection_expression(exprs.projection())
+                .set_filter_expression(exprs.filter_expression())
+                .set_expression_attribute_names(exprs.names())
+                .set_expression_attribute_values(exprs.values())
+                .set_exclusive_start_key(start)
+                .limit(exprs.limit(limit))
+                .send()
+                .await?;
+            Ok((
+                output.items().unwrap_or_default().to_vec(),
+                output.last_evaluated_key().cloned(),
+            ))
+        })
+        .await
+    }
+
+    // Films indexed under `term`, narrowed down by the rest of the query.
+    async fn query_term(
+        &self,
+        term: &str,
+        query: &FilmQuery,
+        page: &Page,
+    ) -> Result<FilmPage, FilmError> {
+        let mut exprs = Expressions::default()
+            .key("#term = :term")
+            .name("#term", "term")
+            .value(":term", AttributeValue::S(term.into()));
+        if let Some(years) = &query.years {
+            // Every film key in the range sorts between the first year's
+            // prefix and the last year's prefix followed by the largest
+            // character.
+            exprs = exprs
+                .key("#film BETWEEN :from AND :to")
+                .name("#film", "film")
+                .value(":from", AttributeValue::S(ddb::year_prefix(*years.start())))
+                .value(
+                    ":to",
+                    AttributeValue::S(format!("{}{}", ddb::year_prefix(*years.end()), char::MAX)),
+                );
+        }
+        let exprs = &with_title(exprs, query, false);
+        let fields = &query.fields_to_read();
+        collect_page(page, INDEX_KEY, |start, limit| async move {
+            let output = self
+                .client
+                .query()
+                .table_name(&self.index_table)
+                .index_name(ddb::TERM_INDEX)
+                .set_key_condition_expression(exprs.key_condition())
+                .set_filter_expression(exprs.filter_expression())
+                .set_expression_attribute_names(exprs.names())
+                .set_expression_attribute_values(exprs.values())
+                .set_exclusive_start_key(start)
+                .limit(exprs.limit(limit))
+                .send()
+                .await?;
+
+            // The films are read and checked against the rest of the query,
+            // e.g. a genre alongside an actor, before the page's offset and
+            // limit count them. Each keeps its index key for the cursor.
+            let indexed = output.items().unwrap_or_default();
+            let keys = indexed
+                .iter()
+                .map(Film::try_from)
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut found: HashMap<_, _> = self
+                .batch_get(&keys, fields.as_ref())
+                .await?
+                .into_iter()
+                .map(|film| ((film.year, film.title.clone()), film))
+                .collect();
+            let mut items = Vec::new();
+            for (index_item, key) in indexed.iter().zip(keys) {
+                let Some(film) = found.remove(&(key.year, key.title)) else {
+                    continue;
+                };
+                if query.matches(&film) {
+                    let mut item: Item = serde_dynamo::to_item(&film)?;
+                    for name in INDEX_KEY {
+                        if let Some(value) = index_item.get(*name) {
+                            item.insert(name.to_string(), value.clone());
+                        }
+                    }
+                    items.push(item);
+                }
+            }
+            Ok((items, output.last_evaluated_key().cloned()))
+        })
+        .await
+    }
+
+    // Reads the films for `keys`, or just their `fields`, keeping their
+    // order. Films that have been deleted since they were indexed are
+    // skipped.
+    async fn batch_get(
+        &self,
+        keys: &[Film],
+        fields: Option<&Fields>,
+    ) -> Result<Vec<Film>, FilmError> {
+        let exprs = Expressions::default().project(fields);
+        let mut found = HashMap::new();
+        for chunk in keys.chunks(BATCH_GET_SIZE) {
+            let keys = chunk
+                .iter()
+                .map(|film| key(film.year, &film.title))
+                .collect();
+            let mut request = HashMap::from([(
+                self.table.clone(),
+                KeysAndAttributes::builder()
+                    .set_keys(Some(keys))
+                    .set_projection_expression(exprs.projection())
+                    .set_expression_attribute_names(exprs.names())
+                    .build(),
+            )]);
+            // Unprocessed keys are retried with backoff, like unprocessed
+            // writes in `ddb::write_batch`.
+            for attempt in 0.. {
+                let unprocessed: usize = request
+                    .values()
+                    .map(|keys| keys.keys().map_or(0, <[_]>::len))
+                    .sum();
+                if unprocessed == 0 {
+                    break;
+                }
+                if attempt == ddb::BATCH_ATTEMPTS {
+                    return Err(ddb::error::Error::throttled(&self.table, unprocessed).into());
+                }
+                if attempt > 0 {
+                    tokio::time::sleep(ddb::backoff(attempt)).await;
+                }
+                let output = self
+                    .client
+                    .batch_get_item()
+                    .set_request_items(Some(request))
+                    .send()
+                    .await?;
+                let items = output.responses().and_then(|r| r.get(&self.table));
+                for item in items.into_iter().flatten() {
+                    let film = Film::try_from(item)?;
+                    found.insert((film.year, film.title.clone()), film);
+                }
+                request = output.unprocessed_keys().cloned().unwrap_or_default();
+            }
+        }
+        Ok(keys
+            .iter()
+            .filter_map(|film| found.remove(&(film.year, film.title.clone())))
+            .collect())
+    }
+
+    // Brings the index items for a film in line with `film`, or removes them
+    // all when the film was deleted.
+    async fn sync_index(
+        &self,
+        year: i32,
+        title: &str,
+        film: Option<&Film>,
+    ) -> Result<(), FilmError> {
+        let terms = film.map(ddb::index_terms).unwrap_or_default();
+        let items: Vec<_> = self
+            .client
+            .query()
+            .table_name(&self.index_table)
+            .key_condition_expression("#film = :film")
+            .expression_attribute_names("#film", "film")
+            .expression_attribute_values(":film", AttributeValue::S(ddb::film_key(year, title)))
+            .into_paginator()
+            .items()
+            .send()
+            .try_collect()
+            .await?;
+        let existing: BTreeSet<String> = items
+            .iter()
+            .filter_map(|item| item.get("term")?.as_s().ok().cloned())
+            .collect();
+
+        let mut ops: Vec<_> = existing
+            .difference(&terms)
+            .map(|term| ddb::index_delete(year, title, term))
+            .collect();
+        if let Some(film) = film {
+            ops.extend(
+                terms
+                    .difference(&existing)
+                    .map(|term| ddb::index_put(film, term)),
+            );
+        }
+        for chunk in ops.chunks(ddb::CHUNK_SIZE) {
+            ddb::write_batch(&self.client, &self.index_table, chunk).await?;
+        }
+        Ok(())
+    }
+
+    // Syncs the index after a film was written. The write has already
+    // happened, so a failure is logged rather than returned, leaving the
+    // film missing from (or stale in) genre and cast lookups.
+    async fn reindex(&self, year: i32, title: &str, film: Option<&Film>) {
+        if let Err(e) = self.sync_index(year, title, film).await {
+            log::warn!("Failed to index film {title} ({year}): {e}");
+        }
+    }
+}
+
+fn key(year: i32, title: &str) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("year".to_string(), AttributeValue::N(year.to_string())),
+        ("title".to_string(), AttributeValue::S(title.into())),
+    ])
+}
+
+#[async_trait]
+impl FilmStore for DynamoFilmStore {
+    async fn get(&self, year: i32, title: &str) -> Result<Option<Film>, FilmError> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .key("year", AttributeValue::N(year.to_string()))
+            .key("title", AttributeValue::S(title.into()))
+            .send()
+            .await?;
+        output.item().map(Film::try_from).transpose()
+    }
+
+    async fn get_fields(
+        &self,
+        year: i32,
+        title: &str,
+        fields: &Fields,
+    ) -> Result<Option<Film>, FilmError> {
+        let exprs = Expressions::default().project(Some(fields));
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .key("year", AttributeValue::N(year.to_string()))
+            .key("title", AttributeValue::S(title.into()))
+            .set_projection_expression(exprs.projection())
+            .set_expression_attribute_names(exprs.names())
+            .send()
+            .await?;
+        output.item().map(Film::try_from).transpose()
+    }
+
+    async fn create(&self, film: &Film) -> Result<(), FilmError> {
+        let putreq = PutRequest::try_from(film)?;
+        match self
+            .client
+            .put_item()
+            .table_name(&self.table)
+            .set_item(putreq.item().cloned())
+            .condition_expression("attribute_not_exists(#title)")
+            .expression_attribute_names("#title", "title")
+            .send()
+            .await
+        {
+            Ok(_) => {
+                self.reindex(film.year, &film.title, Some(film)).await;
+                Ok(())
+            }
+            Err(e) => match e.into_service_error() {
+                e if e.is_conditional_check_failed_exception() => {
+                    Err(FilmError::AlreadyExists(film.title.clone(), film.year))
+                }
+                e => Err(aws_sdk_dynamodb::Error::from(e).into()),
+            },
+        }
+    }
+
+    async fn put(&self, film: &Film) -> Result<(), FilmError> {
+        let putreq = PutRequest::try_from(film)?;
+        self.client
+            .put_item()
+            .table_name(&self.table)
+            .set_item(putreq.item().cloned())
+            .send()
+            .await?;
+        self.reindex(film.year, &film.title, Some(film)).await;
+        Ok(())
+    }
+
+    // BatchWriteItem can't make a write conditional, so existing films are
+    // looked up first. A film created between the lookup and the write is
+    // overwritten.
+    async fn import(&self, films: &[Film], upsert: bool) -> Result<Vec<Imported>, FilmError> {
+        let mut outcomes = repeated_keys(films);
+        if !upsert {
+            // BatchGetItem rejects a request naming the same key twice, so
+            // repeated keys, already conflicted, aren't looked up again.
+            let keys: Vec<Film> = films
+                .iter()
+                .zip(&outcomes)
+                .filter(|(_, outcome)| **outcome == Imported::Created)
+                .map(|(film, _)| Film::new(film.year, film.title.clone()))
+                .collect();
+            // Only whether each film exists matters, so only keys are read.
+            let existing = self.batch_get(&keys, Some(&Fields::key())).await?;
+            let existing: BTreeSet<_> = existing
+                .iter()
+                .map(|film| (film.year, film.title.as_str()))
+                .collect();
+            for (film, outcome) in films.iter().zip(&mut outcomes) {
+                if existing.contains(&(film.year, film.title.as_str())) {
+                    *outcome = Imported::Conflicted;
+                }
+            }
+        }
+
+        let pending: Vec<usize> = (0..films.len())
+            .filter(|&i| outcomes[i] == Imported::Created)
+            .collect();
+        for chunk in pending.chunks(ddb::CHUNK_SIZE) {
+            let written = async {
+                let ops = chunk
+                    .iter()
+                    .map(|&i| {
+                        Ok(WriteRequest::builder()
+                            .put_request(PutRequest::try_from(&films[i])?)
+                            .build())
+                    })
+                    .collect::<Result<Vec<_>, FilmError>>()?;
+                ddb::write_batch(&self.client, &self.table, &ops).await?;
+                Ok::<(), FilmError>(())
+            };
+            if let Err(e) = written.await {
+                log::warn!("Failed to import {} films: {e}", chunk.len());
+                for &i in chunk {
+                    outcomes[i] = Imported::Failed;
+                }
+            }
+        }
+
+        let written: Vec<usize> = (0..films.len())
+            .filter(|&i| outcomes[i] == Imported::Created)
+            .collect();
+        futures::stream::iter(written)
+            .for_each_concurrent(INDEX_SYNCS, |i| {
+                let film = &films[i];
+                self.reindex(film.year, &film.title, Some(film))
+            })
+            .await;
+        Ok(outcomes)
+    }
+
+    async fn list(&self, query: &FilmQuery, page: &Page) -> Result<FilmPage, FilmError> {
+        // Cast members are more selective than genres, so when both are
+        // given the actor's films are read and filtered by genre.
+        let term = match (&query.actor, &query.genre) {
+            (Some(actor), _) => Some(ddb::actor_term(actor)),
+            (None, Some(genre)) => Some(ddb::genre_term(genre)),
+            (None, None) => None,
+        };
+        match (term, &query.years) {
+            (Some(term), _) => self.query_term(&term, query, page).await,
+            (None, Some(years)) if years.start() == years.end() => {
+                self.query_year(*years.start(), query, page).await
+            }
+            (None, Some(years)) => self.query_years(years.clone(), query, page).await,
+            (None, None) => self.scan(query, page).await,
+        }
+    }
+
+    fn export(&self) -> BoxStream<'static, Result<Film, FilmError>> {
+        ddb::scan::parallel_scan(&self.client, &self.table, self.scan_segments)
+            .map(|item| Film::try_from(&item?))
+            .boxed()
+    }
+
+    async fn delete(&self, year: i32, title: &str) -> Result<Option<Film>, FilmError> {
+        let output = self
+            .client
+            .delete_item()
+            .table_name(&self.table)
+            .key("year", AttributeValue::N(year.to_string()))
+            .key("title", AttributeValue::S(title.into()))
+            .condition_expression("attribute_exists(#title)")
+            .expression_attribute_names("#title", "title")
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await;
+        match output {
+            Ok(output) => {
+                self.reindex(year, title, None).await;
+                output.attributes().map(Film::try_from).transpose()
+            }
+            Err(e) => match e.into_service_error() {
+                e if e.is_conditional_check_failed_exception() => Ok(None),
+                e => Err(aws_sdk_dynamodb::Error::from(e).into()),
+            },
+        }
+    }
+
+    async fn update(
+        &self,
+        year: i32,
+        title: &str,
+        patch: &FilmPatch,
+    ) -> Result<Option<Film>, FilmError> {
+        if patch.is_empty() {
+            return self.get(year, title).await;
+        }
+
+        let mut set = Vec::new();
+        let mut remove = Vec::new();
+        let mut request = self
+            .client
+            .update_item()
+            .table_name(&self.table)
+            .key("year", AttributeValue::N(year.to_string()))
+            .key("title", AttributeValue::S(title.into()))
+            .condition_expression("attribute_exists(#title)")
+            .expression_attribute_names("#title", "title")
+            .return_values(ReturnValue::AllNew);
+        for (i, (name, value)) in patch_attributes(patch).into_iter().enumerate() {
+            request = request.expression_attribute_names(format!("#f{i}"), name);
+            match value {
+                Some(value) => {
+                    request = request.expression_attribute_values(format!(":v{i}"), value);
+                    set.push(format!("#f{i} = :v{i}"));
+                }
+                None => remove.push(format!("#f{i}")),
+            }
+        }
+        let mut expression = String::new();
+        if !set.is_empty() {
+            expression.push_str(&format!("SET {} ", set.join(", ")));
+        }
+        if !remove.is_empty() {
+            expression.push_str(&format!("REMOVE {}", remove.join(", ")));
+        }
+
+        match request
+            .update_expression(expression.trim_end())
+            .send()
+            .await
+        {
+            Ok(output) => {
+                let film = output.attributes().map(Film::try_from).transpose()?;
+                if patch.genres.is_some() || patch.cast.is_some() {
+                    self.reindex(year, title, film.as_ref()).await;
+                }
+                Ok(film)
+            }
+            Err(e) => match e.into_service_error() {
+                e if e.is_conditional_check_failed_exception() => Ok(None),
+                e => Err(aws_sdk_dynamodb::Error::from(e).into()),
+            },
+        }
+    }
+}
+
+type Item = HashMap<String, AttributeValue>;
+
+// The key attributes of the films table and of the index table.
+const TABLE_KEY: &[&str] = &["year", "title"];
+const INDEX_KEY: &[&str] = &["film", "term"];
+
+// A filtered read evaluates at least this many items per request, so that
+// sparse matches don't turn into one request per film.
+const FILTERED_READ: i32 = 250;
+
+// The expressions for one Query or Scan, sharing attribute names and values.
+#[derive(Clone, Debug, Default)]
+struct Expressions {
+    key: Vec<String>,
+    filter: Vec<String>,
+    projection: Vec<String>,
+    names: HashMap<String, String>,
+    values: Item,
+}
+
+impl Expressions {
+    fn key(mut self, condition: &str) -> Self {
+        self.key.push(condition.into());
+        self
+    }
+
+    fn filter(mut self, condition: &str) -> Self {
+        self.filter.push(condition.into());
+        self
+    }
+
+    fn name(mut self, placeholder: &str, name: &str) -> Self {
+        self.names.insert(placeholder.into(), name.into());
+        self
+    }
+
+    fn value(mut self, placeholder: &str, value: AttributeValue) -> Self {
+        self.values.insert(placeholder.into(), value);
+        self
+    }
+
+    // Reads only `fields` and the key, which films are parsed and paged by,
+    // rather than every attribute.
+    fn project(mut self, fields: Option<&Fields>) -> Self {
+        let Some(fields) = fields else {
+            return self;
+        };
+        let names = TABLE_KEY.iter().chain(
+            fields
+                .names()
+                .iter()
+                .filter(|name| !TABLE_KEY.contains(name)),
+        );
+        for name in names {
+            let placeholder = format!("#p{}", self.projection.len());
+            self.names.insert(placeholder.clone(), name.to_string());
+            self.projection.push(placeholder);
+        }
+        self
+    }
+
+    fn projection(&self) -> Option<String> {
+        (!self.projection.is_empty()).then(|| self.projection.join(", "))
+    }
+
+    fn key_condition(&self) -> Option<String> {
+        (!self.key.is_empty()).then(|| self.key.join(" AND "))
+    }
+
+    fn filter_expression(&self) -> Option<String> {
+        (!self.filter.is_empty()).then(|| self.filter.join(" AND "))
+    }
+
+    // DynamoDB rejects empty maps, so these are left unset instead.
+    fn names(&self) -> Option<HashMap<String, String>> {
+        (!self.names.is_empty()).then(|| self.names.clone())
+    }
+
+    fn values(&self) -> Option<Item> {
+        (!self.values.is_empty()).then(|| self.values.clone())
+    }
+
+    fn limit(&self, wanted: i32) -> i32 {
+        if self.filter.is_empty() {
+            wanted
+        } else {
+            wanted.max(FILTERED_READ)
+        }
+    }
+}
+
+// Adds the query's title match. Exact and prefix matches use the sort key
+// when `sort_key` is set, otherwise every title match is a filter.
+fn with_title(exprs: Expressions, query: &FilmQuery, sort_key: bool) -> Expressions {
+    let Some(title) = &query.title else {
+        return exprs;
+    };
+    let (condition, name, value) = match query.title_match {
+        TitleMatch::Exact => ("#title = :title", "title", title.clone()),
+        TitleMatch::Prefix => ("begins_with(#title, :title)", "title", title.clone()),
+        TitleMatch::Contains => (
+            "contains(#title, :title)",
+            "title_normalized",
+            normalize(title),
+        ),
+    };
+    let exprs = exprs
+        .name("#title", name)
+        .value(":title", AttributeValue::S(value));
+    if sort_key && query.title_match != TitleMatch::Contains {
+        exprs.key(condition)
+    } else {
+        exprs.filter(condition)
+    }
+}
+
+// Reads `offset + limit` items starting at the page's cursor, calling `fetch`
+// with the key to start from and the number of items still wanted, and
+// returns the last `limit` as films. `key` names the key attributes, used
+// for the cursor when `fetch` returns more items than were wanted.
+async fn collect_page<F, Fut>(
+    page: &Page,
+    key: &[&str],
+    mut fetch: F,
+) -> Result<FilmPage, FilmError>
+where
+    F: FnMut(Option<Item>, i32) -> Fut,
+    Fut: Future<Output = Result<(Vec<Item>, Option<Item>), FilmError>>,
+{
+    let wanted = page.offset.saturating_add(page.limit);
+    let mut start = match &page.cursor {
+        Some(cursor) if cursor.is_key(key) => Some(serde_dynamo::to_item(cursor.key())?),
+        Some(_) => {
+            return Err(FilmError::InvalidCursor(format!(
+                "expected a cursor with {}",
+                key.join(" and ")
+            )))
+        }
+        None => None,
+    };
+    let mut items = Vec::new();
+    while items.len() < wanted {
+        let remaining = (wanted - items.len()).min(i32::MAX as usize) as i32;
+        let (mut batch, last) = fetch(start, remaining).await?;
+        items.append(&mut batch);
+        start = last;
+        if start.is_none() {
+            break;
+        }
+    }
+    if items.len() > wanted {
+        items.truncate(wanted);
+        start = items.last().map(|item| {
+            key.iter()
+                .filter_map(|k| Some((k.to_string(), item.get(*k)?.clone())))
+                .collect()
+        });
+    }
+
+    let next = match start {
+        Some(last) => Some(Cursor::new(serde_dynamo::from_item(last)?)),
+        None => None,
+    };
+    Ok(FilmPage {
+        films: items
+            .iter()
+            .skip(page.offset)
+            .map(Film::try_from)
+            .collect::<Result<_, _>>()?,
+        next,
+    })
+}
+
+// Where one segment of a listing's scan has got to.
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Start,
+    After(Cursor),
+    Done,
+}
+
+impl Segment {
+    // A scan cursor holds every segment's position under `segments`, as the
+    // key to start after, null before the first read, or "done". It must
+    // have one position for each of the `total` segments being scanned.
+    fn from_cursor(cursor: &Cursor, total: usize) -> Result<Vec<Segment>, FilmError> {
+        let invalid =
+            || FilmError::InvalidCursor(format!("expected a scan cursor with {total} segments"));
+        let segments = cursor
+            .key()
+            .get("segments")
+            .and_then(Value::as_array)
+            .filter(|segments| segments.len() == total)
+            .ok_or_else(invalid)?;
+        segments
+            .iter()
+            .map(|segment| match segment {
+                Value::Null => Ok(Segment::Start),
+                Value::Object(key) => Some(Cursor::new(key.clone()))
+                    .filter(|cursor| cursor.is_key(TABLE_KEY))
+                    .map(Segment::After)
+                    .ok_or_else(invalid),
+                Value::String(s) if s == "done" => Ok(Segment::Done),
+                _ => Err(invalid()),
+            })
+            .collect()
+    }
+
+    fn to_cursor(segments: &[Segment]) -> Cursor {
+        let segments = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Start => Value::Null,
+                Segment::After(cursor) => Value::Object(cursor.key().clone()),
+                Segment::Done => Value::from("done"),
+            })
+            .collect();
+        Cursor::new(serde_json::Map::from_iter([(
+            "segments".to_string(),
+            Value::Array(segments),
+        )]))
+    }
+}
+
+// The attributes touched by a patch; `None` means the attribute is removed.
+fn patch_attributes(patch: &FilmPatch) -> Vec<(&'static str, Option<AttributeValue>)> {
+    let string = |v: &Option<String>| v.clone().map(AttributeValue::S);
+    let number = |v: &Option<i32>| v.map(|n| AttributeValue::N(n.to_string()));
+    let list = |v: &Option<Vec<String>>| {
+        v.as_ref()
+            .map(|v| AttributeValue::L(v.iter().map(|s| AttributeValue::S(s.clone())).collect()))
+    };
+
+    let mut attributes = Vec::new();
+    if let Some(genres) = &patch.genres {
+        attributes.push(("genres", list(genres)));
+    }
+    if let Some(cast) = &patch.cast {
+        attributes.push(("cast", list(cast)));
+    }
+    if let Some(href) = &patch.href {
+        attributes.push(("href", string(href)));
+    }
+    if let Some(thumbnail) = &patch.thumbnail {
+        attributes.push(("thumbnail", string(thumbnail)));
+    }
+    if let Some(width) = &patch.thumbnail_width {
+        attributes.push(("thumbnail_width", number(width)));
+    }
+    if let Some(height) = &patch.thumbnail_height {
+        attributes.push(("thumbnail_height", number(height)));
+    }
+    if let Some(extract) = &patch.extract {
+        attributes.push(("extract", string(extract)));
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    };
+
+    use aws_sdk_dynamodb::Client;
+    use serde_json::{json, Value};
+
+    use super::{DynamoFilmStore, Expressions};
+    use crate::{
+        ddb::{
+            self, fake,
+            scan::test::{scan_client, FILMS},
+        },
+        models::{Film, FilmError},
+        store::{Cursor, FilmQuery, FilmStore, Imported, MemoryFilmStore, Page},
+    };
+
+    #[test]
+    fn test_projection_includes_key() {
+        let fields = "extract,title".parse().unwrap();
+        let exprs = Expressions::default().project(Some(&fields));
+        assert_eq!(exprs.projection().unwrap(), "#p0, #p1, #p2");
+        let names = exprs.names().unwrap();
+        assert_eq!(names["#p0"], "year");
+        assert_eq!(names["#p1"], "title");
+        assert_eq!(names["#p2"], "extract");
+
+        assert_eq!(Expressions::default().project(None).projection(), None);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_for_another_table_is_invalid() {
+        let (client, log) = scan_client(FILMS);
+        let store = DynamoFilmStore::new(client, "films");
+        let query = FilmQuery {
+            genre: Some("Mystery".into()),
+            ..FilmQuery::default()
+        };
+        let page = Page {
+            offset: 0,
+            limit: 10,
+            cursor: Some(Cursor::after(&Film::new(2019, "Knives Out".into()))),
+        };
+
+        let result = store.list(&query, &page).await;
+        assert!(matches!(result, Err(FilmError::InvalidCursor(_))));
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_pages_through_every_segment() {
+        let (client, log) = scan_client(FILMS);
+        let store = DynamoFilmStore::new(client, "films").with_scan_segments(3);
+
+        let mut titles = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = Page {
+                offset: 0,
+                limit: 3,
+                cursor,
+            };
+            let films = store.list(&FilmQuery::default(), &page).await.unwrap();
+            assert!(films.films.len() <= 3);
+            titles.extend(films.films.into_iter().map(|film| film.title));
+            cursor = films.next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        titles.sort();
+        let mut expected: Vec<&str> = FILMS.iter().map(|(_, title)| *title).collect();
+        expected.sort();
+        assert_eq!(titles, expected);
+        assert!(log.lock().unwrap().iter().all(|&(_, total)| total == 3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_get_gives_up_on_unprocessed_keys() {
+        let requests = Arc::new(AtomicU32::new(0));
+        let count = requests.clone();
+        let client = fake::client(move |_, body| {
+            count.fetch_add(1, Ordering::SeqCst);
+            let output = json!({"Responses": {}, "UnprocessedKeys": body["RequestItems"]});
+            (200, output)
+        });
+        let store = DynamoFilmStore::new(client, "films");
+
+        let result = store
+            .batch_get(&[Film::new(2019, "Knives Out".into())], None)
+            .await;
+        assert!(matches!(
+            result,
+            Err(FilmError::Table(ddb::error::Error::Throttled(_, 1)))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), ddb::BATCH_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn test_writes_succeed_when_indexing_fails() {
+        let client = fake::client(|operation, _| match operation {
+            "PutItem" | "BatchWriteItem" => (200, json!({})),
+            _ => (500, fake::error("InternalServerError", "index unavailable")),
+        });
+        let store = DynamoFilmStore::new(client, "films");
+
+        let mut film = Film::new(2019, "Knives Out".into());
+        film.genres = vec!["Mystery".into()];
+        store.create(&film).await.unwrap();
+        store.put(&film).await.unwrap();
+        let outcomes = store.import(&[film], true).await.unwrap();
+        assert_eq!(outcomes, [Imported::Created]);
+    }
+
+    #[tokio::test]
+    async fn test_year_range_queries_each_year_in_order() {
+        let years = Arc::new(Mutex::new(Vec::new()));
+        let queried = years.clone();
+        let client = fake::client(move |_, body| {
+            let year: i32 = body["ExpressionAttributeValues"][":yyyy"]["N"]
+                .as_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            queried.lock().unwrap().push(year);
+
+            let mut titles: Vec<&str> = FILMS
+                .iter()
+                .filter(|(y, _)| *y == year)
+                .map(|(_, title)| *title)
+                .collect();
+            titles.sort();
+            let items: Vec<Value> = titles
+                .into_iter()
+                .map(|title| json!({"year": {"N": year.to_string()}, "title": {"S": title}}))
+                .collect();
+            let start = match body.get("ExclusiveStartKey") {
+                Some(key) => items.iter().position(|item| item == key).unwrap() + 1,
+                None => 0,
+            };
+            let limit = body["Limit"].as_u64().unwrap() as usize;
+            let page: Vec<&Value> = items.iter().skip(start).take(limit).collect();
+            let mut output = json!({ "Items": page });
+            if start + page.len() < items.len() {
+                output["LastEvaluatedKey"] = (*page.last().unwrap()).clone();
+            }
+            (200, output)
+        });
+        let store = DynamoFilmStore::new(client, "films");
+        let query = FilmQuery {
+            years: Some(1980..=2000),
+            ..FilmQuery::default()
+        };
+
+        let mut titles = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = Page {
+                offset: 0,
+                limit: 2,
+                cursor,
+            };
+            let films = store.list(&query, &page).await.unwrap();
+            assert!(films.films.len() <= 2);
+            titles.extend(films.films.into_iter().map(|film| film.title));
+            match films.next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(titles, ["Blade Runner", "Aliens", "Heat", "Ronin"]);
+        assert!(years
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|year| (1980..=2000).contains(year)));
+    }
+
+    #[tokio::test]
+    async fn test_import_looks_up_each_key_once() {
+        let client = fake::client(|operation, body| match operation {
+            "BatchGetItem" => {
+                let request = &body["RequestItems"]["films"];
+                // Only the key is read to see whether a film exists.
+                assert_eq!(request["ProjectionExpression"], "#p0, #p1");
+                let keys = request["Keys"].as_array().unwrap();
+                if (1..keys.len()).any(|i| keys[..i].contains(&keys[i])) {
+                    let message = "Provided list of item keys contains duplicates";
+                    return (400, fake::error("ValidationException", message));
+                }
+                let existing = json!({"year": {"N": "1995"}, "title": {"S": "Heat"}});
+                let found: Vec<&Value> = keys.iter().filter(|key| **key == existing).collect();
+                (200, json!({"Responses": {"films": found}}))
+            }
+            "Query" => (200, json!({"Items": []})),
+            _ => (200, json!({})),
+        });
+        let store = DynamoFilmStore::new(client, "films");
+
+        let films = [
+            Film::new(2019, "Knives Out".into()),
+            Film::new(2019, "Knives Out".into()),
+            Film::new(1995, "Heat".into()),
+        ];
+        let outcomes = store.import(&films, false).await.unwrap();
+        assert_eq!(
+            outcomes,
+            [
+                Imported::Created,
+                Imported::Conflicted,
+                Imported::Conflicted
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_cursor_must_match_segments() {
+        let (client, log) = scan_client(FILMS);
+        let store = DynamoFilmStore::new(client, "films").with_scan_segments(3);
+
+        let knives_out = json!({"year": 2019, "title": "Knives Out"});
+        for segments in [
+            json!([null, "done"]),
+            json!([null, "done", {"year": "2019"}]),
+            json!(vec![Value::Null; 1_000_000]),
+        ] {
+            let page = Page {
+                offset: 0,
+                limit: 3,
+                cursor: Some(Cursor::new(serde_json::Map::from_iter([(
+                    "segments".to_string(),
+                    segments,
+                )]))),
+            };
+            let result = store.list(&FilmQuery::default(), &page).await;
+            assert!(matches!(result, Err(FilmError::InvalidCursor(_))));
+        }
+        let page = Page {
+            offset: 0,
+            limit: 3,
+            cursor: Some(Cursor::new(knives_out.as_object().unwrap().clone())),
+        };
+        let result = store.list(&FilmQuery::default(), &page).await;
+        assert!(matches!(result, Err(FilmError::InvalidCursor(_))));
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    // A DynamoDB stand-in holding `films` that answers index Queries a term
+    // at a time and BatchGetItem with the films.
+    fn index_client(films: Vec<Film>) -> Client {
+        let list = |items: &[String]| json!({"L": items.iter().map(|s| json!({"S": s})).collect::<Vec<_>>()});
+        fake::client(move |operation, body| match operation {
+            "Query" => {
+                let term = body["ExpressionAttributeValues"][":term"]["S"]
+                    .as_str()
+                    .unwrap();
+                let mut items: Vec<Value> = films
+                    .iter()
+                    .filter(|film| ddb::index_terms(film).contains(term))
+                    .map(|film| {
+                        json!({
+                            "film": {"S": ddb::film_key(film.year, &film.title)},
+                            "term": {"S": term},
+                            "year": {"N": film.year.to_string()},
+                            "title": {"S": film.title},
+                        })
+                    })
+                    .collect();
+                items.sort_by_key(|item| item["film"]["S"].as_str().unwrap().to_string());
+                let start = match body.get("ExclusiveStartKey") {
+                    Some(key) => {
+                        items
+                            .iter()
+                            .position(|item| item["film"] == key["film"])
+                            .unwrap()
+                            + 1
+                    }
+                    None => 0,
+                };
+                let limit = body["Limit"].as_u64().unwrap() as usize;
+                let page: Vec<&Value> = items.iter().skip(start).take(limit).collect();
+                let mut output = json!({ "Items": page });
+                if start + page.len() < items.len() {
+                    let last = page.last().unwrap();
+                    output["LastEvaluatedKey"] =
+                        json!({"film": last["film"], "term": last["term"]});
+                }
+                (200, output)
+            }
+            "BatchGetItem" => {
+                let keys = body["RequestItems"]["films"]["Keys"].as_array().unwrap();
+                let found: Vec<Value> = films
+                    .iter()
+                    .filter(|film| {
+                        keys.iter().any(|key| {
+                            key["year"]["N"] == film.year.to_string().as_str()
+                                && key["title"]["S"] == film.title.as_str()
+                        })
+                    })
+                    .map(|film| {
+                        json!({
+                            "year": {"N": film.year.to_string()},
+                            "title": {"S": film.title},
+                            "genres": list(&film.genres),
+                            "cast": list(&film.cast),
+                        })
+                    })
+                    .collect();
+                (200, json!({"Responses": {"films": found}}))
+            }
+            _ => (400, fake::error("ValidationException", operation)),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_actor_and_genre_pages_match_memory_store() {
+        let films: Vec<Film> = (0..9)
+            .map(|i| {
+                let mut film = Film::new(2000 + i, format!("Film {i}"));
+                film.cast = vec![if i == 4 {
+                    "Rachel Weisz"
+                } else {
+                    "Daniel Craig"
+                }
+                .into()];
+                film.genres = vec![if i % 2 == 0 { "Comedy" } else { "Drama" }.into()];
+                film
+            })
+            .collect();
+        let memory = MemoryFilmStore::new();
+        for film in &films {
+            memory.put(film).await.unwrap();
+        }
+        let dynamo = DynamoFilmStore::new(index_client(films), "films");
+        let query = FilmQuery {
+            actor: Some("daniel craig".into()),
+            genre: Some("comedy".into()),
+            ..FilmQuery::default()
+        };
+
+        let mut pages = Vec::new();
+        for store in [&memory as &dyn FilmStore, &dynamo] {
+            let mut titles = Vec::new();
+            let mut page = Page {
+                offset: 1,
+                limit: 1,
+                cursor: None,
+            };
+            loop {
+                let films = store.list(&query, &page).await.unwrap();
+                titles.push(
+                    films
+                        .films
+                        .into_iter()
+                        .map(|film| film.title)
+                        .collect::<Vec<_>>(),
+                );
+                match films.next {
+                    Some(next) => page.cursor = Some(next),
+                    None => break,
+                }
+            }
+            pages.push(titles);
+        }
+        assert_eq!(pages[0], [["Film 2"], ["Film 8"]]);
+        assert_eq!(pages[1], pages[0]);
+    }
+
+    #[tokio::test]
+    async fn test_year_range_rejects_other_cursors() {
+        let (client, log) = scan_client(FILMS);
+        let store = DynamoFilmStore::new(client, "films");
+        let query = FilmQuery {
+            years: Some(1980..=2000),
+            ..FilmQuery::default()
+        };
+        for key in [
+            json!({"film": "1995#Heat", "term": "genre#crime"}),
+            json!({"segments": [null, "done"]}),
+        ] {
+            let page = Page {
+                offset: 0,
+                limit: 2,
+                cursor: Some(Cursor::new(key.as_object().unwrap().clone())),
+            };
+            let result = store.list(&query, &page).await;
+            assert!(matches!(result, Err(FilmError::InvalidCursor(_))));
+        }
+        assert!(log.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    ops::Bound,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use parking_lot::RwLock;
+
+use super::{repeated_keys, Cursor, FilmPage, FilmQuery, FilmStore, Imported, Page};
+use crate::models::{Film, FilmError, FilmPatch};
+
+type Key = (i32, String);
+
+// How many films `export` copies out of the map at a time.
+const EXPORT_PAGE_SIZE: usize = 100;
+
+/// `FilmStore` that keeps every film in memory, ordered by (year, title) the
+/// same way the DynamoDB table is. Used by the tests and for running the API
+/// without DynamoDB Local.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFilmStore {
+    films: Arc<RwLock<BTreeMap<Key, Film>>>,
+}
+
+impl MemoryFilmStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn key(year: i32, title: &str) -> Key {
+    (year, title.to_string())
+}
+
+// The first key after the page's cursor. A cursor is exclusive, so the title
+// is followed by the smallest possible suffix. Every listing here pages by
+// film key, so a cursor must be one.
+fn start_key(page: &Page) -> Result<Option<Key>, FilmError> {
+    let Some(cursor) = &page.cursor else {
+        return Ok(None);
+    };
+    match (
+        cursor.is_key(&["year", "title"]),
+        cursor.year(),
+        cursor.title(),
+    ) {
+        (true, Some(year), Some(title)) => Ok(Some(key(year, &format!("{title}\0")))),
+        _ => Err(FilmError::InvalidCursor(
+            "expected a cursor with year and title".into(),
+        )),
+    }
+}
+
+fn paginate<'a>(films: impl Iterator<Item = &'a Film>, page: &Page) -> FilmPage {
+    let mut films: Vec<Film> = films
+        .skip(page.offset)
+        .take(page.limit.saturating_add(1))
+        .cloned()
+        .collect();
+    let next = if films.len() > page.limit {
+        films.truncate(page.limit);
+        films.last().map(Cursor::after)
+    } else {
+        None
+    };
+    FilmPage { films, next }
+}
+
+#[async_trait]
+impl FilmStore for MemoryFilmStore {
+    async fn get(&self, year: i32, title: &str) -> Result<Option<Film>, FilmError> {
+        Ok(self.films.read().get(&key(year, title)).cloned())
+    }
+
+    async fn create(&self, film: &Film) -> Result<(), FilmError> {
+        let mut films = self.films.write();
+        match films.entry(key(film.year, &film.title)) {
+            Entry::Occupied(_) => Err(FilmError::AlreadyExists(film.title.clone(), film.year)),
+            Entry::Vacant(entry) => {
+                entry.insert(film.clone());
+                Ok(())
+            }
+        }
+    }
+
+    async fn put(&self, film: &Film) -> Result<(), FilmError> {
+        self.films
+            .write()
+            .insert(key(film.year, &film.title), film.clone());
+        Ok(())
+    }
+
+    async fn import(&self, films: &[Film], upsert: bool) -> Result<Vec<Imported>, FilmError> {
+        let mut outcomes = repeated_keys(films);
+        let mut stored = self.films.write();
+        for (film, outcome) in films.iter().zip(&mut outcomes) {
+            if *outcome != Imported::Created {
+                continue;
+            }
+            match stored.entry(key(film.year, &film.title)) {
+                Entry::Occupied(mut entry) if upsert => {
+                    entry.insert(film.clone());
+                }
+                Entry::Occupied(_) => *outcome = Imported::Conflicted,
+                Entry::Vacant(entry) => {
+                    entry.insert(film.clone());
+                }
+            }
+        }
+        Ok(outcomes)
+    }
+
+    // Films are copied out a page at a time, so the lock isn't held while
+    // the stream is consumed.
+    fn export(&self) -> BoxStream<'static, Result<Film, FilmError>> {
+        let films = self.films.clone();
+        let pages = stream::unfold(Some(Bound::Unbounded), move |after| {
+            let films = films.clone();
+            async move {
+                let page: Vec<Film> = films
+                    .read()
+                    .range((after?, Bound::Unbounded))
+                    .take(EXPORT_PAGE_SIZE)
+                    .map(|(_, film)| film.clone())
+                    .collect();
+                let next = match page.last() {
+                    Some(last) if page.len() == EXPORT_PAGE_SIZE => {
+                        Some(Bound::Excluded(key(last.year, &last.title)))
+                    }
+                    _ => None,
+                };
+                Some((page, next))
+            }
+        });
+        pages
+            .flat_map(|page| stream::iter(page.into_iter().map(Ok)))
+            .boxed()
+    }
+
+    async fn list(&self, query: &FilmQuery, page: &Page) -> Result<FilmPage, FilmError> {
+        let films = self.films.read();
+        let (from, to) = query
+            .years
+            .as_ref()
+            .map_or((i32::MIN, i32::MAX), |years| (*years.start(), *years.end()));
+        let first = key(from, "");
+        let start = start_key(page)?.map_or(first.clone(), |start| start.max(first));
+        Ok(paginate(
+            films
+                .range(start..)
+                .take_while(|((year, _), _)| *year <= to)
+                .map(|(_, film)| film)
+                .filter(|film| query.matches(film)),
+            page,
+        ))
+    }
+
+    async fn delete(&self, year: i32, title: &str) -> Result<Option<Film>, FilmError> {
+        Ok(self.films.write().remove(&key(year, title)))
+    }
+
+    async fn update(
+        &self,
+        year: i32,
+        title: &str,
+        patch: &FilmPatch,
+    ) -> Result<Option<Film>, FilmError> {
+        let mut films = self.films.write();
+        Ok(films.get_mut(&key(year, title)).map(|film| {
+            patch.apply(film);
+            film.clone()
+        }))
+    }
+}
@@ -0,0 +1,147 @@
+use std::{ops::RangeInclusive, sync::Arc};
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+
+use crate::models::{normalize, Fields, Film, FilmError, FilmPatch, TitleMatch};
+
+mod dynamo;
+mod memory;
+mod page;
+pub use dynamo::DynamoFilmStore;
+pub use memory::MemoryFilmStore;
+pub use page::{Cursor, FilmPage, Page};
+
+/// Filters for listing films. Every filter that is set must match. `fields`
+/// narrows what's read of each film rather than which films match.
+#[derive(Clone, Debug, Default)]
+pub struct FilmQuery {
+    /// Matches films from these years, inclusive.
+    pub years: Option<RangeInclusive<i32>>,
+    /// Matches films listing this genre, ignoring case.
+    pub genre: Option<String>,
+    /// Matches films with this cast member, ignoring case.
+    pub actor: Option<String>,
+    pub title: Option<String>,
+    pub title_match: TitleMatch,
+    /// Only these fields are needed, so a store can leave the rest at their
+    /// defaults. The key is always read.
+    pub fields: Option<Fields>,
+}
+
+impl FilmQuery {
+    /// `fields` and any others `matches` looks at, for stores that filter
+    /// films after reading them.
+    pub fn fields_to_read(&self) -> Option<Fields> {
+        let mut fields = self.fields.clone()?;
+        if self.genre.is_some() {
+            fields = fields.with("genres");
+        }
+        if self.actor.is_some() {
+            fields = fields.with("cast");
+        }
+        Some(fields)
+    }
+
+    pub fn matches(&self, film: &Film) -> bool {
+        self.years
+            .as_ref()
+            .is_none_or(|years| years.contains(&film.year))
+            && self.genre.as_ref().is_none_or(|genre| {
+                let genre = normalize(genre);
+                film.genres.iter().any(|g| normalize(g) == genre)
+            })
+            && self.actor.as_ref().is_none_or(|actor| {
+                let actor = normalize(actor);
+                film.cast.iter().any(|a| normalize(a) == actor)
+            })
+            && self
+                .title
+                .as_ref()
+                .is_none_or(|title| match self.title_match {
+                    TitleMatch::Exact => film.title == *title,
+                    TitleMatch::Prefix => film.title.starts_with(title.as_str()),
+                    TitleMatch::Contains => normalize(&film.title).contains(&normalize(title)),
+                })
+    }
+}
+
+/// The outcome of importing one film.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Imported {
+    Created,
+    /// The film already existed, or came earlier in the same import.
+    Conflicted,
+    /// The film couldn't be written. The cause is logged, not returned.
+    Failed,
+}
+
+/// Storage backend for films, keyed by (year, title) like the `films` table.
+///
+/// A store that keeps a secondary index for genre and cast lookups updates
+/// it after each write. Once the film itself is written, a failure to
+/// update the index is logged and the write still succeeds, so the film
+/// can be missing from filtered listings until it's written again.
+#[async_trait]
+pub trait FilmStore: Send + Sync {
+    /// Fetch a single film by its key.
+    async fn get(&self, year: i32, title: &str) -> Result<Option<Film>, FilmError>;
+
+    /// Fetch a single film by its key, of which only `fields` and the key
+    /// are needed. Stores that can't read less than a whole film get it.
+    async fn get_fields(
+        &self,
+        year: i32,
+        title: &str,
+        _fields: &Fields,
+    ) -> Result<Option<Film>, FilmError> {
+        self.get(year, title).await
+    }
+
+    /// Store a new film, failing with `FilmError::AlreadyExists` if a film
+    /// with the same key is already stored.
+    async fn create(&self, film: &Film) -> Result<(), FilmError>;
+
+    /// Store a film, replacing any film with the same key.
+    async fn put(&self, film: &Film) -> Result<(), FilmError>;
+
+    /// Store many films at once, returning what happened to each, in order.
+    /// Without `upsert`, films that already exist are left alone. A key that
+    /// appears more than once is only stored the first time.
+    async fn import(&self, films: &[Film], upsert: bool) -> Result<Vec<Imported>, FilmError>;
+
+    /// A page of the films matching `query`. Films within a year are
+    /// ordered by title.
+    async fn list(&self, query: &FilmQuery, page: &Page) -> Result<FilmPage, FilmError>;
+
+    /// Every film, read a page at a time as the stream is polled so that
+    /// the whole catalogue is never held in memory.
+    fn export(&self) -> BoxStream<'static, Result<Film, FilmError>>;
+
+    /// Delete a film, returning it if it existed.
+    async fn delete(&self, year: i32, title: &str) -> Result<Option<Film>, FilmError>;
+
+    /// Apply `patch` to an existing film, returning the updated film or
+    /// `None` if there was nothing to update. Never creates a film.
+    async fn update(
+        &self,
+        year: i32,
+        title: &str,
+        patch: &FilmPatch,
+    ) -> Result<Option<Film>, FilmError>;
+}
+
+/// Shared handle to a store, cloned into each filter.
+pub type Store = Arc<dyn FilmStore>;
+
+/// Marks every film whose key appeared earlier in `films` as conflicted.
+fn repeated_keys(films: &[Film]) -> Vec<Imported> {
+    let mut seen = std::collections::HashSet::new();
+    films
+        .iter()
+        .map(|film| match seen.insert((film.year, film.title.as_str())) {
+            true => Imported::Created,
+            false => Imported::Conflicted,
+        })
+        .collect()
+}
@@ -0,0 +1,119 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde_json::{Map, Value};
+
+use crate::models::Film;
+
+/// Which slice of a listing to return.
+#[derive(Clone, Debug, Default)]
+pub struct Page {
+    /// Films to skip after the cursor.
+    pub offset: usize,
+    /// Maximum number of films to return.
+    pub limit: usize,
+    pub cursor: Option<Cursor>,
+}
+
+/// One page of a listing, with a cursor for the next page if there may be
+/// more films.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FilmPage {
+    pub films: Vec<Film>,
+    pub next: Option<Cursor>,
+}
+
+/// Opaque position in a listing: the key of the last film read, in the shape
+/// DynamoDB returns as `LastEvaluatedKey`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor(Map<String, Value>);
+
+impl Cursor {
+    pub fn new(key: Map<String, Value>) -> Self {
+        Cursor(key)
+    }
+
+    /// The cursor positioned just after `film`.
+    pub fn after(film: &Film) -> Self {
+        let mut key = Map::new();
+        key.insert("year".into(), film.year.into());
+        key.insert("title".into(), film.title.clone().into());
+        Cursor(key)
+    }
+
+    pub fn key(&self) -> &Map<String, Value> {
+        &self.0
+    }
+
+    pub fn year(&self) -> Option<i32> {
+        self.0.get("year")?.as_i64()?.try_into().ok()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.0.get("title")?.as_str()
+    }
+
+    /// Whether the cursor is a key with exactly the attributes `names`, where
+    /// `year` is a number and the rest are strings.
+    pub fn is_key(&self, names: &[&str]) -> bool {
+        self.0.len() == names.len()
+            && names.iter().all(|name| match self.0.get(*name) {
+                Some(Value::Number(year)) if *name == "year" => year.is_i64(),
+                Some(Value::String(_)) => *name != "year",
+                _ => false,
+            })
+    }
+
+    /// Whether the cursor has a shape some listing returns: a key of the
+    /// films table or of the index table, or the segments of a scan.
+    pub fn is_well_formed(&self) -> bool {
+        self.is_key(&["year", "title"])
+            || self.is_key(&["film", "term"])
+            || (self.0.len() == 1 && self.0.get("segments").is_some_and(Value::is_array))
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Value::Object(self.0.clone()).to_string())
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let json = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        match serde_json::from_slice(&json).ok()? {
+            Value::Object(key) => Some(Cursor(key)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::Cursor;
+    use crate::models::Film;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = Cursor::after(&Film::new(2019, "Knives Out".into()));
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+
+        assert_eq!(decoded, cursor);
+        assert_eq!(decoded.year(), Some(2019));
+        assert_eq!(decoded.title(), Some("Knives Out"));
+        assert_eq!(Cursor::decode("not a cursor"), None);
+        assert!(decoded.is_well_formed());
+    }
+
+    #[test]
+    fn test_cursor_shape() {
+        let cursor = |json: serde_json::Value| match json {
+            serde_json::Value::Object(key) => Cursor::new(key),
+            _ => unreachable!(),
+        };
+        assert!(
+            cursor(json!({"film": "2019#Knives Out", "term": "genre#mystery"})).is_well_formed()
+        );
+        assert!(cursor(json!({"segments": [null, "done"]})).is_well_formed());
+        assert!(!cursor(json!({"foo": 1})).is_well_formed());
+        assert!(!cursor(json!({"year": "2019", "title": "Knives Out"})).is_well_formed());
+        assert!(!cursor(json!({"year": 2019, "title": "Knives Out", "extra": 1})).is_well_formed());
+    }
+}
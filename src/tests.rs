@@ -1,43 +1,26 @@
-use aws_sdk_dynamodb::{config::Region, Client};
+use std::sync::Arc;
+
 use warp::http::StatusCode;
 use warp::test::request;
 
-use super::{filters, models::Film};
+use super::{
+    error::Problem,
+    filters::{self, DEFAULT_BATCH_LIMIT},
+    models::{Film, FilmList, ImportReport, ImportStatus},
+    store::{Cursor, FilmStore, MemoryFilmStore},
+};
 
 #[tokio::test]
 async fn test_welcome() {
-    let config = aws_config::from_env()
-        .region(Region::new("us-east-1"))
-        .load()
-        .await;
-    let dynamodb_local_config = aws_sdk_dynamodb::config::Builder::from(&config)
-        .endpoint_url(
-            // 8000 is the default dynamodb port
-            "http://localhost:8000",
-        )
-        .build();
-
-    let dbclient = Client::from_conf(dynamodb_local_config);
-    let api = filters::films(dbclient);
+    let api = filters::films(Arc::new(MemoryFilmStore::new()), DEFAULT_BATCH_LIMIT);
     let resp = request().method("GET").path("/").reply(&api).await;
     assert_eq!(resp.status(), StatusCode::OK);
 }
 
 #[tokio::test]
 async fn test_post() {
-    let config = aws_config::from_env()
-        .region(Region::new("us-east-1"))
-        .load()
-        .await;
-    let dynamodb_local_config = aws_sdk_dynamodb::config::Builder::from(&config)
-        .endpoint_url(
-            // 8000 is the default dynamodb port
-            "http://localhost:8000",
-        )
-        .build();
-
-    let dbclient = Client::from_conf(dynamodb_local_config);
-    let api = filters::films(dbclient);
+    let store = MemoryFilmStore::new();
+    let api = filters::films(Arc::new(store.clone()), DEFAULT_BATCH_LIMIT);
 
     let resp = request()
         .method("POST")
@@ -47,8 +30,8 @@ async fn test_post() {
             title: "Coool film".into(),
             genres: vec!["foo".into()],
             cast: vec!["foo".into()],
-            href: Some("".into()),
-            thumbnail: Some("".into()),
+            href: Some("Coool_film".into()),
+            thumbnail: Some("http://example.com/coool.jpg".into()),
             thumbnail_width: Some(2),
             thumbnail_height: Some(4),
             extract: Some("blah blah".into()),
@@ -56,36 +39,776 @@ async fn test_post() {
         .reply(&api)
         .await;
     assert_eq!(resp.status(), StatusCode::CREATED);
+    assert!(store.get(2000, "Coool film").await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_list_by_year() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    store
+        .put(&Film::new(2021, "Another film".into()))
+        .await
+        .unwrap();
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?year=2020")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(list.films, vec![film1()]);
+    assert_eq!(list.next, None);
+
+    let resp = request().method("GET").path("/films").reply(&api).await;
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(list.films.len(), 2);
+}
+
+#[tokio::test]
+async fn test_list_pagination() {
+    let store = MemoryFilmStore::new();
+    for title in ["A", "B", "C", "D", "E"] {
+        store.put(&Film::new(2020, title.into())).await.unwrap();
+    }
+    store.put(&Film::new(2021, "F".into())).await.unwrap();
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?year=2020&offset=1&limit=2")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let link = resp.headers()["link"].to_str().unwrap().to_string();
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    let titles: Vec<_> = list.films.iter().map(|f| f.title.as_str()).collect();
+    assert_eq!(titles, vec!["B", "C"]);
+    let next = list.next.unwrap();
+    assert_eq!(
+        link,
+        format!("</films?limit=2&cursor={next}&year=2020>; rel=\"next\"")
+    );
+
+    let resp = request()
+        .method("GET")
+        .path(&format!("/films?year=2020&limit=2&cursor={next}"))
+        .reply(&api)
+        .await;
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    let titles: Vec<_> = list.films.iter().map(|f| f.title.as_str()).collect();
+    assert_eq!(titles, vec!["D", "E"]);
+    assert_eq!(list.next, None);
+    assert!(!resp.headers().contains_key("link"));
+
+    for path in [
+        "/films?limit=0",
+        "/films?limit=5000",
+        "/films?offset=1000000000000",
+        "/films?cursor=nope",
+        &format!("/films?cursor={}", foreign_cursor()),
+    ] {
+        let resp = request().method("GET").path(path).reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn test_list_by_year_range() {
+    let store = MemoryFilmStore::new();
+    for (year, title) in [
+        (1989, "Batman"),
+        (1990, "Goodfellas"),
+        (1994, "Pulp Fiction"),
+        (1994, "Forrest Gump"),
+        (1999, "The Matrix"),
+        (2000, "Gladiator"),
+    ] {
+        store.put(&Film::new(year, title.into())).await.unwrap();
+    }
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?from_year=1990&to_year=1999&limit=3")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    let titles: Vec<_> = list.films.iter().map(|f| f.title.as_str()).collect();
+    assert_eq!(titles, vec!["Goodfellas", "Forrest Gump", "Pulp Fiction"]);
+
+    let resp = request()
+        .method("GET")
+        .path(&format!(
+            "/films?from_year=1990&to_year=1999&limit=3&cursor={}",
+            list.next.unwrap()
+        ))
+        .reply(&api)
+        .await;
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    let titles: Vec<_> = list.films.iter().map(|f| f.title.as_str()).collect();
+    assert_eq!(titles, vec!["The Matrix"]);
+    assert_eq!(list.next, None);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?from_year=1999")
+        .reply(&api)
+        .await;
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(list.films.len(), 2);
+
+    for path in [
+        "/films?from_year=1999&to_year=1990",
+        "/films?year=1994&from_year=1990",
+        "/films?from_year=-2147483648",
+        "/films?to_year=2147483647",
+        "/films?year=1500",
+        &format!("/films?from_year=1990&cursor={}", term_cursor()),
+        &format!("/films?from_year=1990&cursor={}", scan_cursor()),
+    ] {
+        let resp = request().method("GET").path(path).reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn test_list_by_genre() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    let mut comedy = Film::new(2021, "Another film".into());
+    comedy.genres_mut().push("comedy".into());
+    store.put(&comedy).await.unwrap();
+    store.put(&Film::new(2021, "Drama".into())).await.unwrap();
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?genre=Comedy")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(list.films, vec![film1(), comedy.clone()]);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?genre=Comedy&year=2021")
+        .reply(&api)
+        .await;
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(list.films, vec![comedy]);
+}
+
+#[tokio::test]
+async fn test_list_by_title() {
+    let store = MemoryFilmStore::new();
+    for (year, title) in [
+        (2019, "Knives Out"),
+        (2022, "Glass Onion: A Knives Out Mystery"),
+        (2019, "Knives"),
+        (2019, "Ford v Ferrari"),
+    ] {
+        store.put(&Film::new(year, title.into())).await.unwrap();
+    }
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let titles = |resp: warp::http::Response<warp::hyper::body::Bytes>| {
+        let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+        list.films.into_iter().map(|f| f.title).collect::<Vec<_>>()
+    };
+
+    let resp = request()
+        .method("GET")
+        .path("/films?title=knives%20out")
+        .reply(&api)
+        .await;
+    assert_eq!(
+        titles(resp),
+        vec!["Knives Out", "Glass Onion: A Knives Out Mystery"]
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/films?title=Knives&match=prefix&year=2019")
+        .reply(&api)
+        .await;
+    assert_eq!(titles(resp), vec!["Knives", "Knives Out"]);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?title=Knives&match=exact")
+        .reply(&api)
+        .await;
+    assert_eq!(titles(resp), vec!["Knives"]);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?title=knives&match=prefix")
+        .reply(&api)
+        .await;
+    assert!(titles(resp).is_empty());
 }
 
-// #[tokio::test]
-// async fn test_post_conflict() {
-//     let db = models::blank_db();
-//     db.lock().await.push(film1());
-//     let api = filters::films(db);
-
-//     let resp = request()
-//         .method("POST")
-//         .path("/films")
-//         .json(&film1())
-//         .reply(&api)
-//         .await;
-
-//     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
-// }
-
-// fn film1() -> Film {
-//     let genres = vec!["Comedy".into(), "Horror".into()];
-
-//     Film {
-//         title: "Some film".into(),
-//         year: 2020,
-//         cast: vec!["Person One".into(), "Person Two".into()],
-//         genres: genres,
-//         href: Some("Some_film".into()),
-//         extract: Some("This is a dummy film".into()),
-//         thumbnail: Some("http://example.com/1.jpg".into()),
-//         thumbnail_width: Some(200),
-//         thumbnail_height: Some(327),
-//     }
-// }
+#[tokio::test]
+async fn test_list_by_actor() {
+    let store = MemoryFilmStore::new();
+    let mut skyfall = Film::new(2012, "Skyfall".into());
+    skyfall.cast_mut().push("Daniel Craig".into());
+    skyfall.genres_mut().push("Action".into());
+    let mut knives_out = Film::new(2019, "Knives Out".into());
+    knives_out.cast_mut().push("Daniel Craig".into());
+    knives_out.genres_mut().push("Comedy".into());
+    store.put(&skyfall).await.unwrap();
+    store.put(&knives_out).await.unwrap();
+    store.put(&film1()).await.unwrap();
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?actor=daniel%20craig")
+        .reply(&api)
+        .await;
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(list.films, vec![skyfall.clone(), knives_out.clone()]);
+
+    let resp = request()
+        .method("GET")
+        .path("/actors/Daniel%20Craig/films?genre=comedy")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let list: FilmList = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(list.films, vec![knives_out]);
+
+    let resp = request()
+        .method("GET")
+        .path("/actors/Daniel%20Craig/films?limit=1")
+        .reply(&api)
+        .await;
+    let link = resp.headers()["link"].to_str().unwrap();
+    assert!(link.starts_with("</actors/Daniel%20Craig/films?limit=1&cursor="));
+}
+
+#[tokio::test]
+async fn test_get_film() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    store.put(&Film::new(2001, "Amélie".into())).await.unwrap();
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films/2020/Some%20film")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let film: Film = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(film, film1());
+
+    let resp = request()
+        .method("GET")
+        .path("/films/2001/Am%C3%A9lie")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = request()
+        .method("GET")
+        .path("/films/2001/Am%C3%A")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = request()
+        .method("GET")
+        .path("/films/2021/Some%20film")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let problem: Problem = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(problem.status, 404);
+    assert_eq!(problem.detail, "film not found: Some film (2021)");
+
+    let resp = request()
+        .method("GET")
+        .path("/films?cursor=bogus")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(resp.headers()["content-type"], "application/problem+json");
+}
+
+#[tokio::test]
+async fn test_sparse_fields() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films/2020/Some%20film?fields=title,year")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let film: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(
+        film,
+        serde_json::json!({"year": 2020, "title": "Some film"})
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/films?year=2020&fields=cast,title")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let list: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(
+        list,
+        serde_json::json!({"films": [{"title": "Some film", "cast": ["Person One", "Person Two"]}]})
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/films?year=2020&fields=cast,title&format=csv")
+        .reply(&api)
+        .await;
+    assert_eq!(
+        std::str::from_utf8(resp.body()).unwrap(),
+        "title,cast\r\nSome film,Person One|Person Two\r\n"
+    );
+
+    for path in [
+        "/films?fields=title,plot",
+        "/films?fields=",
+        "/films/2020/Some%20film?fields=rating",
+    ] {
+        let resp = request().method("GET").path(path).reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{path}");
+        let problem: Problem = serde_json::from_slice(resp.body()).unwrap();
+        assert!(problem.detail.starts_with("unknown field"), "{path}");
+    }
+}
+
+#[tokio::test]
+async fn test_put_replaces_film() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    let api = filters::films(Arc::new(store.clone()), DEFAULT_BATCH_LIMIT);
+
+    let mut update = Film::new(2020, "Some film".into());
+    update.genres_mut().push("Drama".into());
+    let resp = request()
+        .method("PUT")
+        .path("/films/2020/Some%20film")
+        .json(&update)
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(store.get(2020, "Some film").await.unwrap(), Some(update));
+
+    let resp = request()
+        .method("PUT")
+        .path("/films/1999/Missing")
+        .json(&Film::new(1999, "Missing".into()))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(store.get(1999, "Missing").await.unwrap(), None);
+
+    let resp = request()
+        .method("PUT")
+        .path("/films/2020/Some%20film")
+        .json(&Film::new(2021, "Some film".into()))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_patch_merges_film() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    let api = filters::films(Arc::new(store.clone()), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("PATCH")
+        .path("/films/2020/Some%20film")
+        .header("content-type", "application/merge-patch+json")
+        .body(r#"{"extract": null, "cast": ["Person Three"]}"#)
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let mut expected = film1();
+    expected.extract = None;
+    expected.cast = vec!["Person Three".into()];
+    assert_eq!(store.get(2020, "Some film").await.unwrap(), Some(expected));
+
+    let resp = request()
+        .method("PATCH")
+        .path("/films/1999/Missing")
+        .body(r#"{"extract": "Nope"}"#)
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_delete_film() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    store
+        .put(&Film::new(2021, "Another film".into()))
+        .await
+        .unwrap();
+    let api = filters::films(Arc::new(store.clone()), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("DELETE")
+        .path("/films/2020/Some%20film")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert!(resp.body().is_empty());
+    assert_eq!(store.get(2020, "Some film").await.unwrap(), None);
+
+    let resp = request()
+        .method("DELETE")
+        .path("/films/2020/Some%20film")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = request()
+        .method("DELETE")
+        .path("/films/2021/Another%20film")
+        .header("prefer", "return=representation")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let film: Film = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(film, Film::new(2021, "Another film".into()));
+}
+
+#[tokio::test]
+async fn test_post_conflict() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    let api = filters::films(Arc::new(store.clone()), DEFAULT_BATCH_LIMIT);
+
+    let mut changed = film1();
+    changed.extract = Some("Overwritten".into());
+    let resp = request()
+        .method("POST")
+        .path("/films")
+        .json(&changed)
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    assert_eq!(resp.headers()["content-type"], "application/problem+json");
+    let problem: Problem = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(problem.status, 409);
+    assert_eq!(problem.title, "Conflict");
+    assert_eq!(store.get(2020, "Some film").await.unwrap(), Some(film1()));
+
+    let resp = request()
+        .method("POST")
+        .path("/films?upsert=true")
+        .json(&changed)
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(store.get(2020, "Some film").await.unwrap(), Some(changed));
+}
+
+#[tokio::test]
+async fn test_invalid_film() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    let api = filters::films(Arc::new(store.clone()), DEFAULT_BATCH_LIMIT);
+
+    let mut invalid = Film::new(0, "".into());
+    invalid.cast = vec!["Person One".into(), "Person One".into()];
+    invalid.href = Some("not a link".into());
+    let resp = request()
+        .method("POST")
+        .path("/films")
+        .json(&invalid)
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(resp.headers()["content-type"], "application/problem+json");
+    let problem: Problem = serde_json::from_slice(resp.body()).unwrap();
+    let fields: Vec<_> = problem.errors.iter().map(|e| e.field.as_str()).collect();
+    assert_eq!(fields, ["title", "year", "cast[1]", "href"]);
+    assert!(store.get(0, "").await.unwrap().is_none());
+
+    let resp = request()
+        .method("PATCH")
+        .path("/films/2020/Some%20film")
+        .header("content-type", "application/merge-patch+json")
+        .body(r#"{"thumbnail": "/1.jpg"}"#)
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let problem: Problem = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(problem.errors[0].field, "thumbnail");
+    assert_eq!(store.get(2020, "Some film").await.unwrap(), Some(film1()));
+}
+
+#[tokio::test]
+async fn test_batch_import() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    let api = filters::films(Arc::new(store.clone()), DEFAULT_BATCH_LIMIT);
+
+    let body = serde_json::json!([
+        {"year": 2021, "title": "New film", "genres": ["Comedy"]},
+        film1(),
+        {"year": 0, "title": "Bad year"},
+        {"title": "No year"},
+        {"year": 2021, "title": "New film"},
+    ]);
+    let resp = request()
+        .method("POST")
+        .path("/films/batch")
+        .json(&body)
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report: ImportReport = serde_json::from_slice(resp.body()).unwrap();
+    let statuses: Vec<_> = report.items.iter().map(|item| item.status).collect();
+    assert_eq!(
+        statuses,
+        [
+            ImportStatus::Created,
+            ImportStatus::Conflicted,
+            ImportStatus::Invalid,
+            ImportStatus::Invalid,
+            ImportStatus::Conflicted,
+        ]
+    );
+    assert_eq!(
+        (report.created, report.conflicted, report.invalid),
+        (1, 2, 2)
+    );
+    assert_eq!(report.items[2].errors[0].field, "year");
+    assert!(store.get(2021, "New film").await.unwrap().is_some());
+    assert_eq!(store.get(2020, "Some film").await.unwrap(), Some(film1()));
+
+    // NDJSON, one film per line.
+    let mut changed = film1();
+    changed.extract = Some("Overwritten".into());
+    let body = format!(
+        "{}\n\n{}\n",
+        serde_json::to_string(&changed).unwrap(),
+        r#"{"year": 2022, "title": "Another film"}"#
+    );
+    let resp = request()
+        .method("POST")
+        .path("/films/batch?upsert=true")
+        .header("content-type", "application/x-ndjson")
+        .body(body)
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report: ImportReport = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(report.created, 2);
+    assert_eq!(store.get(2020, "Some film").await.unwrap(), Some(changed));
+}
+
+#[tokio::test]
+async fn test_export() {
+    let store = MemoryFilmStore::new();
+    // More than one of the memory store's export pages.
+    for n in 0..250 {
+        store
+            .put(&Film::new(1900 + n % 100, format!("Film {n:03}")))
+            .await
+            .unwrap();
+    }
+    store.put(&film1()).await.unwrap();
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films/export")
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "application/x-ndjson");
+    let body = std::str::from_utf8(resp.body()).unwrap();
+    let films: Vec<Film> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(films.len(), 251);
+    assert!(films
+        .windows(2)
+        .all(|w| (w[0].year, &w[0].title) < (w[1].year, &w[1].title)));
+    assert_eq!(films.last(), Some(&film1()));
+}
+
+#[tokio::test]
+async fn test_batch_import_limit() {
+    let api = filters::films(Arc::new(MemoryFilmStore::new()), 64);
+    let films: Vec<_> = (0..10)
+        .map(|n| Film::new(2000, format!("Film {n}")))
+        .collect();
+    let resp = request()
+        .method("POST")
+        .path("/films/batch")
+        .json(&films)
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(resp.headers()["content-type"], "application/problem+json");
+
+    let resp = request()
+        .method("POST")
+        .path("/films/batch")
+        .body("{}")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_list_formats() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    store.put(&Film::new(2020, "Zebra".into())).await.unwrap();
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?year=2020&limit=1")
+        .header("accept", "text/csv")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "text/csv; charset=utf-8");
+    assert_eq!(resp.headers()["vary"], "accept");
+    assert_eq!(
+        std::str::from_utf8(resp.body()).unwrap(),
+        "year,title,genres,cast,href,thumbnail,thumbnail_width,thumbnail_height,extract\r\n\
+         2020,Some film,Comedy|Horror,Person One|Person Two,Some_film,http://example.com/1.jpg,200,327,This is a dummy film\r\n"
+    );
+    let link = resp.headers()["link"].to_str().unwrap();
+    assert!(link.contains("year=2020"), "{link}");
+
+    // ?format= wins over Accept, and is kept in the next page's link.
+    let resp = request()
+        .method("GET")
+        .path("/actors/Person%20One/films?format=ndjson")
+        .header("accept", "text/csv")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.headers()["content-type"], "application/x-ndjson");
+    let body = std::str::from_utf8(resp.body()).unwrap();
+    let films: Vec<Film> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(films, [film1()]);
+
+    let resp = request()
+        .method("GET")
+        .path("/films?year=2020&limit=1&format=csv")
+        .reply(&api)
+        .await;
+    let link = resp.headers()["link"].to_str().unwrap();
+    assert!(link.contains("format=csv"), "{link}");
+
+    let resp = request()
+        .method("GET")
+        .path("/films")
+        .header("accept", "text/html")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(resp.headers()["content-type"], "application/problem+json");
+
+    let resp = request()
+        .method("GET")
+        .path("/films?format=xml")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_export_formats() {
+    let store = MemoryFilmStore::new();
+    store.put(&film1()).await.unwrap();
+    store.put(&Film::new(2021, "Zebra".into())).await.unwrap();
+    let api = filters::films(Arc::new(store), DEFAULT_BATCH_LIMIT);
+
+    let resp = request()
+        .method("GET")
+        .path("/films/export")
+        .header("accept", "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.headers()["content-type"], "application/json");
+    let films: Vec<Film> = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(films, [film1(), Film::new(2021, "Zebra".into())]);
+
+    let resp = request()
+        .method("GET")
+        .path("/films/export?format=csv")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.headers()["content-type"], "text/csv; charset=utf-8");
+    let body = std::str::from_utf8(resp.body()).unwrap();
+    let rows: Vec<_> = body.lines().collect();
+    assert_eq!(rows.len(), 3);
+    assert!(rows[0].starts_with("year,title,genres,cast,"));
+    assert_eq!(rows[2], "2021,Zebra,,,,,,,");
+}
+
+// A cursor that decodes but isn't anything a listing returns.
+fn foreign_cursor() -> String {
+    let key = serde_json::json!({"foo": 1});
+    Cursor::new(key.as_object().unwrap().clone()).encode()
+}
+
+// Well-formed cursors from a genre or cast listing and from a scan.
+fn term_cursor() -> String {
+    let key = serde_json::json!({"film": "1994#Pulp Fiction", "term": "genre#crime"});
+    Cursor::new(key.as_object().unwrap().clone()).encode()
+}
+
+fn scan_cursor() -> String {
+    let key = serde_json::json!({"segments": [null, "done"]});
+    Cursor::new(key.as_object().unwrap().clone()).encode()
+}
+
+fn film1() -> Film {
+    let genres = vec!["Comedy".into(), "Horror".into()];
+
+    Film {
+        title: "Some film".into(),
+        year: 2020,
+        cast: vec!["Person One".into(), "Person Two".into()],
+        genres,
+        href: Some("Some_film".into()),
+        extract: Some("This is a dummy film".into()),
+        thumbnail: Some("http://example.com/1.jpg".into()),
+        thumbnail_width: Some(200),
+        thumbnail_height: Some(327),
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::models::{normalize, Film, FilmPatch, MAX_YEAR, MIN_YEAR};
+
+// Limits on what a film can hold. Lengths are in characters.
+const MAX_TITLE_LEN: usize = 300;
+// The title is the films table's sort key, and the index table's `film` key
+// is `YYYY#title`. DynamoDB caps either at 1024 bytes of UTF-8.
+const MAX_TITLE_BYTES: usize = 1024 - "YYYY#".len();
+const MAX_GENRES: usize = 20;
+const MAX_GENRE_LEN: usize = 100;
+const MAX_CAST: usize = 200;
+const MAX_NAME_LEN: usize = 200;
+const MAX_URL_LEN: usize = 2048;
+const MAX_EXTRACT_LEN: usize = 10_000;
+const MAX_THUMBNAIL_SIZE: i32 = 10_000;
+
+/// One field that failed validation, e.g. `cast[2]`, and what's wrong with it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Checks a request body before it's stored, reporting every invalid field
+/// rather than just the first.
+pub trait Validate {
+    fn validate(&self) -> Result<(), Vec<FieldError>>;
+}
+
+impl Validate for Film {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        errors.check_title(&self.title);
+        errors.check_year(self.year);
+        errors.check_genres(&self.genres);
+        errors.check_cast(&self.cast);
+        errors.check_href(self.href.as_deref());
+        errors.check_thumbnail(self.thumbnail.as_deref());
+        errors.check_thumbnail_size("thumbnail_width", self.thumbnail_width);
+        errors.check_thumbnail_size("thumbnail_height", self.thumbnail_height);
+        errors.check_extract(self.extract.as_deref());
+        errors.finish()
+    }
+}
+
+/// Only the fields a patch sets are checked. Removing a field with `null`
+/// is always allowed.
+impl Validate for FilmPatch {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        if let Some(Some(genres)) = &self.genres {
+            errors.check_genres(genres);
+        }
+        if let Some(Some(cast)) = &self.cast {
+            errors.check_cast(cast);
+        }
+        if let Some(href) = &self.href {
+            errors.check_href(href.as_deref());
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            errors.check_thumbnail(thumbnail.as_deref());
+        }
+        if let Some(width) = self.thumbnail_width {
+            errors.check_thumbnail_size("thumbnail_width", width);
+        }
+        if let Some(height) = self.thumbnail_height {
+            errors.check_thumbnail_size("thumbnail_height", height);
+        }
+        if let Some(extract) = &self.extract {
+            errors.check_extract(extract.as_deref());
+        }
+        errors.finish()
+    }
+}
+
+#[derive(Default)]
+struct Errors(Vec<FieldError>);
+
+impl Errors {
+    fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    fn finish(self) -> Result<(), Vec<FieldError>> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self.0)
+        }
+    }
+
+    fn check_title(&mut self, title: &str) {
+        self.check_text("title", title, MAX_TITLE_LEN);
+        if title.len() > MAX_TITLE_BYTES {
+            self.add(
+                "title",
+                format!("must be at most {MAX_TITLE_BYTES} bytes of UTF-8"),
+            );
+        }
+    }
+
+    fn check_year(&mut self, year: i32) {
+        if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+            self.add("year", format!("must be between {MIN_YEAR} and {MAX_YEAR}"));
+        }
+    }
+
+    fn check_genres(&mut self, genres: &[String]) {
+        self.check_list("genres", genres, MAX_GENRES, MAX_GENRE_LEN);
+    }
+
+    fn check_cast(&mut self, cast: &[String]) {
+        self.check_list("cast", cast, MAX_CAST, MAX_NAME_LEN);
+    }
+
+    // Wikipedia Movie Data links films by article name, e.g.
+    // `Knives_Out`, so an href is either that or an absolute URL.
+    fn check_href(&mut self, href: Option<&str>) {
+        let Some(href) = href else { return };
+        if href.contains("://") {
+            self.check_url("href", Some(href));
+        } else if href.is_empty() || href.chars().count() > MAX_URL_LEN {
+            self.add(
+                "href",
+                format!("must be between 1 and {MAX_URL_LEN} characters"),
+            );
+        } else if href.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            self.add(
+                "href",
+                "must be an http(s) URL or an article name without spaces",
+            );
+        }
+    }
+
+    fn check_thumbnail(&mut self, thumbnail: Option<&str>) {
+        self.check_url("thumbnail", thumbnail);
+    }
+
+    fn check_thumbnail_size(&mut self, field: &str, size: Option<i32>) {
+        if size.is_some_and(|size| !(1..=MAX_THUMBNAIL_SIZE).contains(&size)) {
+            self.add(
+                field,
+                format!("must be between 1 and {MAX_THUMBNAIL_SIZE} pixels"),
+            );
+        }
+    }
+
+    fn check_extract(&mut self, extract: Option<&str>) {
+        if extract.is_some_and(|extract| extract.chars().count() > MAX_EXTRACT_LEN) {
+            self.add(
+                "extract",
+                format!("must be at most {MAX_EXTRACT_LEN} characters"),
+            );
+        }
+    }
+
+    fn check_url(&mut self, field: &str, value: Option<&str>) {
+        let Some(value) = value else { return };
+        let valid = value.len() <= MAX_URL_LEN
+            && Url::parse(value)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some());
+        if !valid {
+            self.add(field, "must be an absolute http or https URL");
+        }
+    }
+
+    fn check_text(&mut self, field: &str, value: &str, max_len: usize) {
+        if value.trim().is_empty() {
+            self.add(field, "must not be empty");
+        } else if value.chars().count() > max_len {
+            self.add(field, format!("must be at most {max_len} characters"));
+        } else if value.chars().any(char::is_control) {
+            self.add(field, "must not contain control characters");
+        }
+    }
+
+    // Entries must be valid text, and unique ignoring case.
+    fn check_list(&mut self, field: &str, values: &[String], max_entries: usize, max_len: usize) {
+        if values.len() > max_entries {
+            self.add(field, format!("must have at most {max_entries} entries"));
+        }
+        let mut seen = HashSet::new();
+        for (i, value) in values.iter().enumerate() {
+            let entry = format!("{field}[{i}]");
+            self.check_text(&entry, value, max_len);
+            if !seen.insert(normalize(value)) {
+                self.add(entry, format!("duplicates an earlier entry: {value}"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FieldError, Validate};
+    use crate::models::{Film, FilmPatch};
+
+    fn fields(errors: Vec<FieldError>) -> Vec<String> {
+        errors.into_iter().map(|e| e.field).collect()
+    }
+
+    #[test]
+    fn test_valid_film() {
+        let mut film = Film::new(2019, "Knives Out".into());
+        film.cast = vec!["Daniel Craig".into(), "Chris Evans".into()];
+        film.genres = vec!["Mystery".into()];
+        film.href = Some("Knives_Out".into());
+        film.thumbnail = Some("https://upload.wikimedia.org/knives.jpg".into());
+        film.thumbnail_width = Some(220);
+        film.thumbnail_height = Some(326);
+        assert_eq!(film.validate(), Ok(()));
+
+        film.href = Some("https://en.wikipedia.org/wiki/Knives_Out".into());
+        assert_eq!(film.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_film_reports_every_field() {
+        let mut film = Film::new(99999, " ".into());
+        film.cast = vec!["Daniel Craig".into(), "daniel craig".into()];
+        film.genres = vec!["".into()];
+        film.href = Some("not a link".into());
+        film.thumbnail = Some("ftp://example.com/knives.jpg".into());
+        film.thumbnail_width = Some(-1);
+
+        assert_eq!(
+            fields(film.validate().unwrap_err()),
+            [
+                "title",
+                "year",
+                "genres[0]",
+                "cast[1]",
+                "href",
+                "thumbnail",
+                "thumbnail_width"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_title_fits_in_a_key() {
+        // 300 characters, but 1200 bytes.
+        let film = Film::new(2019, "𝄞".repeat(300));
+        let errors = film.validate().unwrap_err();
+        assert_eq!(fields(errors.clone()), ["title"]);
+        assert!(errors[0].message.contains("bytes"), "{errors:?}");
+
+        assert_eq!(Film::new(2019, "é".repeat(300)).validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_patch_checks_only_set_fields() {
+        let patch: FilmPatch =
+            serde_json::from_str(r#"{"href": null, "thumbnail_height": 0}"#).unwrap();
+        assert_eq!(fields(patch.validate().unwrap_err()), ["thumbnail_height"]);
+        assert_eq!(FilmPatch::default().validate(), Ok(()));
+    }
+}